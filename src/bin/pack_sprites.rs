@@ -0,0 +1,86 @@
+// Splits the GIFs in assets/raw into packed atlases in assets/textures.
+//
+//     cargo run --bin pack_sprites                         # every assets/raw/*.gif
+//     cargo run --bin pack_sprites -- assets/raw/fish_1.gif
+//
+// Each GIF produces `<name>.png` and `<name>.atlas.ron` (frame rects and delays).
+
+#[path = "../systems/atlas.rs"] mod atlas;
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use image::{AnimationDecoder, RgbaImage, imageops};
+use image::codecs::gif::GifDecoder;
+use atlas::*;
+
+const RAW_DIR: &str = "assets/raw";
+const OUT_DIR: &str = "assets/textures";
+const PADDING: u32 = 2;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut inputs: Vec<PathBuf> = std::env::args().skip(1).map(PathBuf::from).collect();
+    if inputs.is_empty() {
+        for entry in fs::read_dir(RAW_DIR)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "gif") { inputs.push(path) }
+        }
+        inputs.sort();
+    }
+
+    for input in inputs {
+        let name = input.file_stem().ok_or("input has no file name")?.to_string_lossy().into_owned();
+        let out = Path::new(OUT_DIR).join(&name);
+        let atlas = pack_gif(&input, &out)?;
+        println!("{} -> {}.png ({} frames, cell {}x{})", input.display(), out.display(), atlas.frames.len(), atlas.cell.0, atlas.cell.1);
+    }
+    Ok(())
+}
+
+fn pack_gif(input: &Path, out: &Path) -> Result<AtlasFile, Box<dyn Error>> {
+    let decoder = GifDecoder::new(BufReader::new(File::open(input)?))?;
+    let frames = decoder.into_frames().collect_frames()?;
+    if frames.is_empty() { return Err(format!("{} has no frames", input.display()).into()) }
+
+    // Trim every frame by the same box so the animation stays aligned.
+    let (min, max) = frames.iter()
+        .filter_map(|f| opaque_bounds(f.buffer()))
+        .reduce(|(a0, a1), (b0, b1)| ((a0.0.min(b0.0), a0.1.min(b0.1)), (a1.0.max(b1.0), a1.1.max(b1.1))))
+        .ok_or_else(|| format!("{} is fully transparent", input.display()))?;
+    let cell = (max.0 - min.0 + 1, max.1 - min.1 + 1);
+
+    let count = frames.len() as u32;
+    let columns = (count as f32).sqrt().ceil() as u32;
+    let rows = count.div_ceil(columns);
+    let size = (columns * (cell.0 + PADDING) - PADDING, rows * (cell.1 + PADDING) - PADDING);
+
+    let mut image = RgbaImage::new(size.0, size.1);
+    let source = frames[0].buffer().dimensions();
+    let mut atlas = AtlasFile { size, cell, source, trim: min, frames: Vec::new() };
+    for (index, frame) in frames.iter().enumerate() {
+        let index = index as u32;
+        let x = (index % columns) * (cell.0 + PADDING);
+        let y = (index / columns) * (cell.1 + PADDING);
+        let trimmed = imageops::crop_imm(frame.buffer(), min.0, min.1, cell.0, cell.1).to_image();
+        imageops::replace(&mut image, &trimmed, x as i64, y as i64);
+
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        let delay = numer as f32 / denom.max(1) as f32 / 1000.0;
+        atlas.frames.push(AtlasFrame { rect: (x, y, cell.0, cell.1), delay });
+    }
+
+    image.save(out.with_extension("png"))?;
+    let ron = ron::ser::to_string_pretty(&atlas, ron::ser::PrettyConfig::default())?;
+    fs::write(out.with_extension("atlas.ron"), ron)?;
+    Ok(atlas)
+}
+
+fn opaque_bounds(image: &RgbaImage) -> Option<((u32, u32), (u32, u32))> {
+    image.enumerate_pixels()
+        .filter(|(_, _, pixel)| pixel.0[3] > 0)
+        .fold(None, |bounds, (x, y, _)| match bounds {
+            None => Some(((x, y), (x, y))),
+            Some((min, max)) => Some(((min.0.min(x), min.1.min(y)), (max.0.max(x), max.1.max(y)))),
+        })
+}
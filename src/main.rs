@@ -1,19 +1,55 @@
+#![allow(clippy::type_complexity, clippy::too_many_arguments)]
+
 #[path = "systems/player.rs"] mod player;
 #[path = "systems/animation.rs"] mod animation;
+#[path = "systems/atlas.rs"] mod atlas;
+#[path = "systems/state_machine.rs"] mod state_machine;
 #[path = "systems/hover.rs"] mod hover;
 #[path = "systems/ui.rs"] mod ui;
 #[path = "systems/scene.rs"] mod scene;
 #[path = "systems/enemy.rs"] mod enemy;
+#[path = "systems/steering.rs"] mod steering;
+#[path = "systems/perception.rs"] mod perception;
+#[path = "systems/spawner.rs"] mod spawner;
+#[path = "systems/health.rs"] mod health;
+#[path = "systems/game_state.rs"] mod game_state;
+#[path = "systems/assets.rs"] mod assets;
+#[path = "systems/input.rs"] mod input;
+#[path = "systems/trajectory.rs"] mod trajectory;
+#[path = "systems/tether.rs"] mod tether;
+#[path = "systems/camera.rs"] mod camera;
+#[path = "systems/level.rs"] mod level;
+#[path = "systems/map.rs"] mod map;
+#[path = "systems/layers.rs"] mod layers;
+#[path = "systems/collision.rs"] mod collision;
 #[path = "./macros/mod.rs"] mod macros;
 
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
 use player::*;
 use animation::*;
+use state_machine::*;
 use ui::*;
 use scene::*;
 use hover::*;
 use enemy::*;
+use steering::*;
+use perception::*;
+use spawner::*;
+use health::*;
+use game_state::*;
+use assets::*;
+use input::*;
+use trajectory::*;
+use tether::*;
+use camera::*;
+use level::*;
+use map::*;
+use layers::*;
+use collision::*;
+use bevy_enhanced_input::prelude::*;
+use bevy_asset_loader::prelude::*;
+use iyes_progress::prelude::*;
 use bevy::sprite::Material2dPlugin;
 use noisy_bevy::NoisyShaderPlugin;
 
@@ -29,32 +65,133 @@ fn main() {
             }),
             ..default()
         })
-        .set(ImagePlugin::default_nearest());
+        .set(ImagePlugin::default_nearest())
+        .set(AssetPlugin {
+            watch_for_changes_override: Some(cfg!(all(debug_assertions, not(target_arch = "wasm32")))),
+            ..default()
+        });
 
     App::new()
         .insert_resource(ClearColor(Color::srgb(1.0, 1.0, 1.0)))
         .insert_resource(DragState::default())
-        .add_event::<HoveredEvent>()
+        .init_resource::<HoverState>()
+        .insert_resource(InputBindings::load())
+        .init_resource::<Rebinding>()
+        .init_resource::<GamepadFling>()
+        .init_resource::<VirtualJoystick>()
+        .init_resource::<SpawnTable>()
+        .init_resource::<Spawner>()
+        .init_resource::<TetherConfig>()
+        .add_event::<HoverEnterEvent>()
+        .add_event::<HoverExitEvent>()
         .add_event::<DragEndedEvent>()
+        .add_event::<FishBiteEvent>()
+        .add_event::<DamageEvent>()
+        .add_event::<DeathEvent>()
+        .add_event::<BallHitEvent>()
+        .add_event::<MapTriggerEvent>()
+        .add_event::<Collided<PlayerFish, Player>>()
+        .add_event::<Collided<Ball, Fish>>()
+        .add_event::<Collided<Ball, Wall>>()
+        .add_event::<Collided<MapTrigger, Player>>()
+        .add_event::<Collided<MapTrigger, Ball>>()
+        .add_event::<AnimationFinished<PlayerAnimation>>()
+        .add_event::<AnimationFinished<FishAnimation>>()
+        .add_event::<AnimationMarker<PlayerAnimation>>()
+        .add_event::<AnimationMarker<FishAnimation>>()
         .add_plugins(default)
+        .init_asset::<AnimationSheet>()
+        .init_asset_loader::<AnimationSheetLoader>()
+        .init_asset::<LevelMap>()
+        .init_asset_loader::<LevelMapLoader>()
         .add_plugins(Material2dPlugin::<CustomMaterial>::default())
         .add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
         .add_plugins(RapierDebugRenderPlugin::default())
         .add_plugins(NoisyShaderPlugin)
-        .add_systems(Startup, setup_scene)
-        .add_systems(Startup, setup_ui)
-        .add_systems(Startup, setup_player)
-        .add_systems(Startup, spawn_player_fish)
-        .add_systems(Update, player_movement)
-        .add_systems(Update, check_hover_system)
-        .add_systems(Update, click_start_drag_system)
-        .add_systems(Update, click_end_drag_system)
-        .add_systems(Update, animate_sprite::<PlayerAnimation>)
-        .add_systems(Update, animate_sprite::<FishAnimation>)
-        .add_systems(Update, apply_drag_impulse_system)
-        .add_systems(Update, fish_follow_player_system)
-        .add_systems(Update, fish_follow_ball_system)
-        .add_systems(Update, detect_playerfish_collision_system)
+        .add_plugins(EnhancedInputPlugin)
+        .add_input_context::<Player>()
+        .add_observer(bind_player_input)
+        .init_state::<GameState>()
+        .add_computed_state::<InGame>()
+        .enable_state_scoped_entities::<GameState>()
+        .enable_state_scoped_entities::<InGame>()
+        .add_plugins(ProgressPlugin::<GameState>::new().with_state_transition(GameState::Loading, GameState::MainMenu))
+        .add_loading_state(LoadingState::new(GameState::Loading)
+            .on_failure_continue_to_state(GameState::LoadingFailed)
+            .load_collection::<TextureAssets>()
+            .load_collection::<AnimationAssets>()
+            .load_collection::<LevelAssets>()
+            .load_collection::<FontAssets>()
+            .load_collection::<ShaderAssets>())
+        .add_systems(OnEnter(GameState::Boot), setup_camera)
+        .add_systems(OnEnter(GameState::Boot), finish_boot)
+        .add_systems(OnEnter(GameState::Loading), setup_loading_screen)
+        .add_systems(Update, update_loading_bar_system.run_if(in_state(GameState::Loading)))
+        .add_systems(OnEnter(GameState::LoadingFailed), setup_loading_failed)
+        .add_systems(OnEnter(GameState::MainMenu), setup_main_menu)
+        .add_systems(Update, main_menu_input_system.run_if(in_state(GameState::MainMenu)))
+        .add_systems(OnEnter(InGame), reset_run)
+        .add_systems(OnEnter(InGame), setup_scene)
+        .add_systems(OnEnter(InGame), setup_ui)
+        .add_systems(OnEnter(InGame), setup_joystick_ui)
+        .add_systems(OnEnter(InGame), setup_player)
+        .add_systems(OnEnter(InGame), spawn_map_fish)
+        .add_systems(PostUpdate, route_collisions_system::<PlayerFish, Player>.after(PhysicsSet::Writeback))
+        .add_systems(PostUpdate, route_collisions_system::<Ball, Fish>.after(PhysicsSet::Writeback))
+        .add_systems(PostUpdate, route_collisions_system::<Ball, Wall>.after(PhysicsSet::Writeback))
+        .add_systems(PostUpdate, route_collisions_system::<MapTrigger, Player>.after(PhysicsSet::Writeback))
+        .add_systems(PostUpdate, route_collisions_system::<MapTrigger, Ball>.after(PhysicsSet::Writeback))
+        .add_systems(PostUpdate, check_collision_layers_system.run_if(|| cfg!(debug_assertions)))
+        .add_systems(OnEnter(InGame), reset_camera_rig)
+        .add_systems(Update, pause_input_system.run_if(in_state(InGame)))
+        .add_systems(OnEnter(GameState::Paused), setup_pause_menu)
+        .add_systems(OnEnter(GameState::Paused), pause_physics)
+        .add_systems(Update, rebind_input_system.after(pause_input_system).run_if(in_state(GameState::Paused)))
+        .add_systems(Update, update_bindings_text_system.after(rebind_input_system).run_if(in_state(GameState::Paused)))
+        .add_systems(OnExit(GameState::Paused), cancel_rebinding)
+        .add_systems(OnExit(GameState::Paused), resume_physics)
+        .add_systems(OnEnter(GameState::GameOver), setup_game_over)
+        .add_systems(Update, game_over_input_system.run_if(in_state(GameState::GameOver)))
+        .add_systems(Update, player_movement.run_if(in_state(GameState::Playing)))
+        .add_systems(Update, check_hover_system.run_if(in_state(GameState::Playing)))
+        .add_systems(Update, hover_highlight_system.after(check_hover_system).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, click_start_drag_system.after(check_hover_system).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, click_end_drag_system.run_if(in_state(GameState::Playing)))
+        .add_systems(Update, update_animation_state::<PlayerAnimation>.after(player_movement).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, update_animation_state::<FishAnimation>.run_if(in_state(GameState::Playing)))
+        .add_systems(Update, animate_sprite::<PlayerAnimation>.after(update_animation_state::<PlayerAnimation>).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, animate_sprite::<FishAnimation>.after(update_animation_state::<FishAnimation>).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, gamepad_fling_system.run_if(in_state(GameState::Playing)))
+        .add_systems(Update, touch_drag_system.run_if(in_state(GameState::Playing)))
+        .add_systems(Update, virtual_joystick_system.after(touch_drag_system).before(player_movement).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, update_joystick_ui_system.after(virtual_joystick_system).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, drag_preview_system.after(click_start_drag_system).after(touch_drag_system).after(gamepad_fling_system).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, apply_drag_impulse_system.after(click_end_drag_system).after(gamepad_fling_system).after(touch_drag_system).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, reel_tether_system.run_if(in_state(GameState::Playing)))
+        .add_systems(Update, tether_break_system.run_if(in_state(GameState::Playing)))
+        .add_systems(Update, draw_tether_system.run_if(in_state(InGame)))
+        .add_systems(Update, spawn_enemies_system.run_if(in_state(GameState::Playing)))
+        .add_systems(Update, despawn_far_enemies_system.run_if(in_state(GameState::Playing)))
+        .add_systems(Update, acquire_target_system::<Player>.run_if(in_state(GameState::Playing)))
+        .add_systems(Update, acquire_target_system::<Ball>.run_if(in_state(GameState::Playing)))
+        .add_systems(Update, perception_system.after(acquire_target_system::<Player>).after(acquire_target_system::<Ball>).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, fish_attack_system.after(perception_system).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, steering_system.after(fish_attack_system).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, detect_playerfish_collision_system.run_if(in_state(GameState::Playing)))
+        .add_systems(Update, detect_ballfish_collision_system.after(fish_attack_system).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, map_trigger_system.run_if(in_state(GameState::Playing)))
+        .add_systems(Update, stunned_fish_system.after(steering_system).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, fish_bite_frame_system.after(animate_sprite::<FishAnimation>).before(fish_attack_system).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, fish_bite_damage_system.after(fish_attack_system).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, apply_damage_system.after(detect_playerfish_collision_system).after(fish_bite_damage_system).after(detect_ballfish_collision_system).after(player_movement).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, invulnerability_flash_system.after(apply_damage_system).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, player_death_system.after(apply_damage_system).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, fish_death_system.after(apply_damage_system).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, update_health_text_system.after(apply_damage_system).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, camera_trauma_system.after(apply_damage_system).after(detect_ballfish_collision_system).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, stream_level_system.after(camera_follow_system).run_if(in_state(InGame)))
+        .add_systems(Update, camera_follow_system.after(camera_trauma_system).after(player_movement).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, game_over_on_death_system.after(apply_damage_system).run_if(in_state(GameState::Playing)))
         .run();
 }
 
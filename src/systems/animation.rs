@@ -1,29 +1,368 @@
 use std::collections::HashMap;
+use std::time::Duration;
 use bevy::prelude::*;
-use std::hash::Hash;
+use bevy_rapier2d::prelude::*;
+use bevy::asset::{AssetLoader, LoadContext, ReadAssetBytesError, io::Reader};
+use bevy::sprite::Anchor;
+use serde::Deserialize;
+use thiserror::Error;
+use crate::atlas::*;
 
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PlaybackMode {
+    // Wraps back to the first frame forever.
+    #[default]
+    Loop,
+    // Plays once, then rests on the first frame.
+    Once,
+    // Goes first -> last -> first forever.
+    PingPong,
+    // Plays once, then rests on the last frame.
+    HoldLast,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct FrameMarker {
+    pub frame: usize,
+    pub name: String,
+}
+
+#[derive(Deserialize, Clone, Debug)]
 pub struct AnimationSlice {
     pub first: usize,
     pub last: usize,
+    // Falls back to the per-frame delays of a packed atlas when omitted.
+    #[serde(default)]
+    pub frame_duration: Option<f32>,
+    #[serde(default)]
+    pub mode: PlaybackMode,
+    #[serde(default)]
+    pub markers: Vec<FrameMarker>,
+    // When set, the clip plays at 1x when the body moves at this speed and scales linearly with it.
+    #[serde(default)]
+    pub speed_from_velocity: Option<f32>,
+}
+
+// Atlas and clips of a sprite sheet, read from a `.anim.ron` file.
+// The atlas layout is exposed as the `layout` labeled asset of the same file.
+#[derive(Asset, TypePath, Debug)]
+pub struct AnimationSheet {
+    pub layout: Handle<TextureAtlasLayout>,
+    pub clips: HashMap<String, AnimationSlice>,
+    pub frame_delays: Vec<f32>,
+    pub cell: Vec2,
+    pub source: Vec2,
+    // Keeps the center of the untrimmed source frame at the entity origin.
+    pub anchor: Vec2,
+}
+
+impl AnimationSheet {
+    pub fn frame_duration(&self, clip: &AnimationSlice, index: usize) -> f32 {
+        clip.frame_duration.or_else(|| self.frame_delays.get(index).copied()).unwrap_or(0.1)
+    }
+}
+
+#[derive(Deserialize)]
+enum AtlasSource {
+    Grid { columns: u32, rows: u32, cell: (u32, u32) },
+    // Path to an `.atlas.ron` written by the `pack_sprites` tool.
+    Packed(String),
+}
+
+#[derive(Deserialize)]
+struct AnimationSheetFile {
+    atlas: AtlasSource,
+    clips: HashMap<String, AnimationSlice>,
+}
+
+#[derive(Default)]
+pub struct AnimationSheetLoader;
+
+#[derive(Debug, Error)]
+pub enum AnimationSheetLoaderError {
+    #[error("could not read animation sheet: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse animation sheet: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("could not read packed atlas: {0}")]
+    Atlas(#[from] ReadAssetBytesError),
+    #[error("clip `{0}` is out of the atlas bounds")]
+    ClipOutOfBounds(String),
+    #[error("clip `{0}` has no frame duration and the atlas has no frame delays")]
+    MissingFrameDuration(String),
+    #[error("clip `{0}` has a frame duration that is not a positive number of seconds")]
+    InvalidFrameDuration(String),
+    #[error("frame {0} of the packed atlas has a delay that is not a positive number of seconds")]
+    InvalidFrameDelay(usize),
+    #[error("clip `{0}` has a `speed_from_velocity` that is not a positive speed")]
+    InvalidSpeedReference(String),
+}
+
+// Anything else panics once it reaches `Duration::from_secs_f32` or the playback speed scale.
+fn valid_seconds(seconds: f32) -> bool {
+    seconds > 0.0 && Duration::try_from_secs_f32(seconds).is_ok()
+}
+
+fn check_clips(clips: &HashMap<String, AnimationSlice>, frame_count: usize, frame_delays: &[f32]) -> Result<(), AnimationSheetLoaderError> {
+    if let Some(frame) = frame_delays.iter().position(|delay| !valid_seconds(*delay)) {
+        return Err(AnimationSheetLoaderError::InvalidFrameDelay(frame));
+    }
+    for (name, clip) in clips {
+        let markers_in_clip = clip.markers.iter().all(|m| (clip.first..=clip.last).contains(&m.frame));
+        if clip.first > clip.last || clip.last >= frame_count || !markers_in_clip {
+            return Err(AnimationSheetLoaderError::ClipOutOfBounds(name.clone()));
+        }
+        match clip.frame_duration {
+            Some(duration) if !valid_seconds(duration) => return Err(AnimationSheetLoaderError::InvalidFrameDuration(name.clone())),
+            None if frame_delays.is_empty() => return Err(AnimationSheetLoaderError::MissingFrameDuration(name.clone())),
+            _ => {}
+        }
+        if clip.speed_from_velocity.is_some_and(|reference| !(reference > 0.0 && reference.is_finite())) {
+            return Err(AnimationSheetLoaderError::InvalidSpeedReference(name.clone()));
+        }
+    }
+    Ok(())
+}
+
+impl AssetLoader for AnimationSheetLoader {
+    type Asset = AnimationSheet;
+    type Settings = ();
+    type Error = AnimationSheetLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<AnimationSheet, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let file: AnimationSheetFile = ron::de::from_bytes(&bytes)?;
+
+        let (layout, frame_delays, atlas) = match file.atlas {
+            AtlasSource::Grid { columns, rows, cell } => {
+                let cell = UVec2::new(cell.0, cell.1);
+                let layout = TextureAtlasLayout::from_grid(cell, columns, rows, None, None);
+                (layout, Vec::new(), AtlasFile { size: (cell.x * columns, cell.y * rows), cell: cell.into(), source: cell.into(), trim: (0, 0), frames: Vec::new() })
+            }
+            AtlasSource::Packed(path) => {
+                let atlas: AtlasFile = ron::de::from_bytes(&load_context.read_asset_bytes(path).await?)?;
+                let mut layout = TextureAtlasLayout::new_empty(atlas.size.into());
+                for (x, y, w, h) in atlas.frames.iter().map(|f| f.rect) {
+                    layout.add_texture(URect::new(x, y, x + w, y + h));
+                }
+                (layout, atlas.frames.iter().map(|f| f.delay).collect(), atlas)
+            }
+        };
+
+        check_clips(&file.clips, layout.len(), &frame_delays)?;
+
+        let cell = Vec2::new(atlas.cell.0 as f32, atlas.cell.1 as f32);
+        let source = Vec2::new(atlas.source.0 as f32, atlas.source.1 as f32);
+        let source_center = source / 2.0 - Vec2::new(atlas.trim.0 as f32, atlas.trim.1 as f32);
+        Ok(AnimationSheet {
+            layout: load_context.add_labeled_asset("layout".into(), layout),
+            clips: file.clips,
+            frame_delays,
+            cell,
+            source,
+            anchor: Vec2::new(source_center.x / cell.x - 0.5, 0.5 - source_center.y / cell.y),
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["anim.ron"]
+    }
+}
+
+// Maps an animation enum variant to its clip name in the sheet.
+pub trait AnimationKey: Send + Sync + 'static + Copy + PartialEq {
+    fn clip_name(&self) -> &'static str;
 }
 
 #[derive(Component)]
 pub struct Animator<T> {
     pub current: T,
     pub timer: Timer,
-    pub clips: HashMap<T, AnimationSlice>,
+    pub sheet: Handle<AnimationSheet>,
+    pub finished: bool,
+    pub backwards: bool,
+    pub speed: f32,
+    pub playing: Option<T>,
+    // Displayed height of an untrimmed source frame, the sprite size follows the sheet cell.
+    pub height: Option<f32>,
+}
+
+// Sent when a `Once` or `HoldLast` clip reaches its end, and after every cycle of a looping clip.
+#[derive(Event)]
+pub struct AnimationFinished<T> {
+    pub entity: Entity,
+    pub clip: T,
+}
+
+// Sent when a clip enters a frame tagged with a marker in the sheet.
+#[derive(Event)]
+pub struct AnimationMarker<T> {
+    pub entity: Entity,
+    pub clip: T,
+    pub name: String,
+}
+
+impl<T> Animator<T> {
+    pub fn from_sheet(sheet: Handle<AnimationSheet>, current: T) -> Self {
+        Self {
+            current,
+            timer: Timer::from_seconds(0.1, TimerMode::Repeating),
+            sheet,
+            finished: false,
+            backwards: false,
+            speed: 1.0,
+            playing: None,
+            height: None,
+        }
+    }
+
+    pub fn with_height(mut self, height: f32) -> Self {
+        self.height = Some(height);
+        self
+    }
 }
 
 pub fn animate_sprite<T>(
     time: Res<Time>,
-    mut query: Query<(&mut Animator<T>, &mut Sprite)>,
-) where T: Send + Sync + 'static + Eq + PartialEq + Hash {
-    for (mut manager, mut sprite) in &mut query {
-        manager.timer.tick(time.delta());
-        if !manager.timer.just_finished() { continue; }
+    sheets: Res<Assets<AnimationSheet>>,
+    mut finished_events: EventWriter<AnimationFinished<T>>,
+    mut marker_events: EventWriter<AnimationMarker<T>>,
+    velocities: Query<&Velocity>,
+    mut query: Query<(Entity, &mut Animator<T>, &mut Sprite, Option<&Parent>)>,
+) where T: AnimationKey {
+    for (entity, mut manager, mut sprite, parent) in &mut query {
+        let current = manager.current;
+        let Some(sheet) = sheets.get(&manager.sheet) else { continue };
+        let Some(clip) = sheet.clips.get(current.clip_name()) else { continue };
+        if let Some(height) = manager.height {
+            let size = sheet.cell * (height / sheet.source.y);
+            if sprite.custom_size != Some(size) {
+                sprite.custom_size = Some(size);
+                sprite.anchor = Anchor::Custom(sheet.anchor);
+            }
+        }
         let Some(atlas) = &mut sprite.texture_atlas else { continue };
-        let Some(indices) = manager.clips.get(&manager.current) else { continue };
-        let reset = atlas.index < indices.first || atlas.index >= indices.last;
-        atlas.index = if reset { indices.first } else {  atlas.index + 1 };
+        if atlas.layout != sheet.layout { atlas.layout = sheet.layout.clone() }
+
+        if manager.playing != Some(current) {
+            manager.playing = Some(current);
+            manager.finished = false;
+            manager.backwards = false;
+            atlas.index = clip.first;
+            manager.timer.set_duration(Duration::from_secs_f32(sheet.frame_duration(clip, atlas.index)));
+            manager.timer.reset();
+        } else {
+            let duration = Duration::from_secs_f32(sheet.frame_duration(clip, atlas.index));
+            if manager.timer.duration() != duration { manager.timer.set_duration(duration) }
+            let scale = clip.speed_from_velocity.map_or(1.0, |reference| body_speed(entity, parent, &velocities) / reference);
+            let speed = (manager.speed * scale).max(0.0);
+            manager.timer.tick(time.delta().mul_f32(speed));
+            if !manager.timer.just_finished() { continue; }
+            match step_frame(&mut manager, &mut atlas.index, clip) {
+                FrameStep::Advanced => {}
+                FrameStep::Wrapped => { finished_events.send(AnimationFinished { entity, clip: current }); }
+                FrameStep::Held => continue,
+                FrameStep::Finished => {
+                    finished_events.send(AnimationFinished { entity, clip: current });
+                    continue;
+                }
+            }
+        }
+
+        for marker in clip.markers.iter().filter(|m| m.frame == atlas.index) {
+            marker_events.send(AnimationMarker { entity, clip: current, name: marker.name.clone() });
+        }
+    }
+}
+
+// Sprites are often children of the rigid body, so fall back to the parent velocity.
+pub fn body_speed(entity: Entity, parent: Option<&Parent>, velocities: &Query<&Velocity>) -> f32 {
+    velocities.get(entity).ok()
+        .or_else(|| parent.and_then(|p| velocities.get(p.get()).ok()))
+        .map_or(0.0, |v| v.linvel.length())
+}
+
+enum FrameStep {
+    Advanced,
+    // A looping clip is back on its first frame.
+    Wrapped,
+    Held,
+    Finished,
+}
+
+fn step_frame<T>(manager: &mut Animator<T>, index: &mut usize, clip: &AnimationSlice) -> FrameStep {
+    if *index < clip.first || *index > clip.last {
+        *index = clip.first;
+    } else if manager.finished {
+        return FrameStep::Held;
+    } else if manager.backwards {
+        manager.backwards = *index > clip.first;
+        *index = if manager.backwards { *index - 1 } else { (clip.first + 1).min(clip.last) };
+        if manager.backwards && *index == clip.first { return FrameStep::Wrapped }
+    } else if *index < clip.last {
+        *index += 1;
+    } else {
+        match clip.mode {
+            PlaybackMode::Loop => {
+                *index = clip.first;
+                return FrameStep::Wrapped;
+            }
+            PlaybackMode::PingPong => {
+                manager.backwards = clip.last > clip.first;
+                *index = clip.last.saturating_sub(1).max(clip.first);
+                if *index == clip.first { return FrameStep::Wrapped }
+            }
+            PlaybackMode::Once | PlaybackMode::HoldLast => {
+                manager.finished = true;
+                if clip.mode == PlaybackMode::Once { *index = clip.first }
+                return FrameStep::Finished;
+            }
+        }
     }
-}
\ No newline at end of file
+    FrameStep::Advanced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clip(source: &str) -> HashMap<String, AnimationSlice> {
+        HashMap::from([("Idle".to_string(), ron::de::from_str(source).unwrap())])
+    }
+
+    #[test]
+    fn loader_rejects_timings_that_would_panic_on_playback() {
+        assert!(check_clips(&clip("(first: 0, last: 1, frame_duration: Some(0.1))"), 2, &[]).is_ok());
+        for duration in ["0.0", "-0.1", "inf", "NaN", "1e30"] {
+            let clips = clip(&format!("(first: 0, last: 1, frame_duration: Some({duration}))"));
+            assert!(matches!(check_clips(&clips, 2, &[]), Err(AnimationSheetLoaderError::InvalidFrameDuration(_))), "{duration}");
+        }
+        assert!(matches!(
+            check_clips(&clip("(first: 0, last: 1)"), 2, &[0.1, 0.0]),
+            Err(AnimationSheetLoaderError::InvalidFrameDelay(1))
+        ));
+        assert!(matches!(
+            check_clips(&clip("(first: 0, last: 1, speed_from_velocity: Some(0.0))"), 2, &[0.1, 0.1]),
+            Err(AnimationSheetLoaderError::InvalidSpeedReference(_))
+        ));
+    }
+
+    #[test]
+    fn looping_clips_finish_once_per_cycle() {
+        let mut animator = Animator::from_sheet(Handle::default(), ());
+        for (source, steps) in [("(first: 0, last: 2)", 3), ("(first: 0, last: 2, mode: PingPong)", 4), ("(first: 0, last: 1, mode: PingPong)", 2)] {
+            let clip: AnimationSlice = ron::de::from_str(source).unwrap();
+            let mut index = clip.first;
+            let wraps = (0..steps * 3)
+                .filter(|_| matches!(step_frame(&mut animator, &mut index, &clip), FrameStep::Wrapped))
+                .count();
+            assert_eq!((wraps, index), (3, clip.first), "{source}");
+        }
+    }
+}
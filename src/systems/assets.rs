@@ -0,0 +1,46 @@
+use bevy::prelude::*;
+use bevy_asset_loader::prelude::*;
+use crate::animation::*;
+use crate::map::*;
+
+#[derive(AssetCollection, Resource)]
+pub struct TextureAssets {
+    #[asset(path = "textures/player.png")]
+    pub player: Handle<Image>,
+    #[asset(path = "textures/fish_1.png")]
+    pub fish: Handle<Image>,
+    #[asset(path = "textures/ball.png")]
+    pub ball: Handle<Image>,
+}
+
+#[derive(AssetCollection, Resource)]
+pub struct AnimationAssets {
+    #[asset(path = "animations/player.anim.ron")]
+    pub player: Handle<AnimationSheet>,
+    #[asset(path = "animations/player.anim.ron#layout")]
+    pub player_layout: Handle<TextureAtlasLayout>,
+    #[asset(path = "animations/fish_1.anim.ron")]
+    pub fish: Handle<AnimationSheet>,
+    #[asset(path = "animations/fish_1.anim.ron#layout")]
+    pub fish_layout: Handle<TextureAtlasLayout>,
+}
+
+#[derive(AssetCollection, Resource)]
+pub struct LevelAssets {
+    #[asset(path = "levels/start.tmj")]
+    pub start: Handle<LevelMap>,
+}
+
+#[derive(AssetCollection, Resource)]
+pub struct FontAssets {
+    #[asset(path = "fonts/JetBrainsMono-Regular.ttf")]
+    pub main: Handle<Font>,
+}
+
+// Only kept so a broken shader fails the loading screen instead of rendering nothing.
+#[derive(AssetCollection, Resource)]
+pub struct ShaderAssets {
+    #[asset(path = "shaders/background.wgsl")]
+    #[allow(dead_code)]
+    pub background: Handle<Shader>,
+}
@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+// Metadata written by the `pack_sprites` tool next to each packed atlas PNG.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AtlasFile {
+    pub size: (u32, u32),
+    pub cell: (u32, u32),
+    // Size of the untrimmed source frames and where the trimmed cell starts in them.
+    pub source: (u32, u32),
+    pub trim: (u32, u32),
+    pub frames: Vec<AtlasFrame>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AtlasFrame {
+    // x, y, width, height in atlas pixels.
+    pub rect: (u32, u32, u32, u32),
+    // Delay of the source GIF frame, in seconds.
+    pub delay: f32,
+}
@@ -0,0 +1,162 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use noisy_bevy::simplex_noise_2d_seeded;
+use crate::player::*;
+use crate::tether::*;
+use crate::scene::*;
+use crate::health::*;
+use crate::enemy::*;
+use crate::collision::*;
+use crate::layers::*;
+
+// Rope stretched past this fraction of its length counts as taut.
+const TAUT_RATIO: f32 = 0.95;
+// Room kept around the diver and the ball when framing both.
+const FRAMING_MARGIN: f32 = 250.0;
+const MAX_ZOOM_OUT: f32 = 2.0;
+// Ball impacts on walls softer than this don't shake the camera.
+const WALL_IMPACT_THRESHOLD: f32 = 50_000_000.0;
+const WALL_IMPACT_PER_TRAUMA: f32 = 400_000_000.0;
+
+#[derive(Component)]
+pub struct CameraRig {
+    // Half size of the box the diver can move in before the camera follows.
+    pub dead_zone: Vec2,
+    // Seconds of diver velocity the camera leads by.
+    pub look_ahead: f32,
+    pub max_look_ahead: f32,
+    // Higher catches up faster.
+    pub smoothing: f32,
+    // 0 to 1, squared into the shake strength and drained over time.
+    pub trauma: f32,
+    pub trauma_decay: f32,
+    pub max_shake_offset: f32,
+    pub max_shake_angle: f32,
+    focus: Vec2,
+    // Unshaken camera position, none until it first snaps to the diver.
+    position: Option<Vec2>,
+    zoom: f32,
+}
+
+impl Default for CameraRig {
+    fn default() -> Self {
+        Self {
+            dead_zone: Vec2::new(60.0, 40.0),
+            look_ahead: 0.35,
+            max_look_ahead: 200.0,
+            smoothing: 4.0,
+            trauma: 0.0,
+            trauma_decay: 1.2,
+            max_shake_offset: 30.0,
+            max_shake_angle: 0.05,
+            focus: Vec2::ZERO,
+            position: None,
+            zoom: 1.0,
+        }
+    }
+}
+
+impl CameraRig {
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).min(1.0);
+    }
+}
+
+// Starts every run on the diver instead of panning over from wherever the last one ended.
+pub fn reset_camera_rig(mut cameras: Query<&mut CameraRig>) {
+    for mut rig in &mut cameras {
+        rig.trauma = 0.0;
+        rig.position = None;
+    }
+}
+
+pub fn camera_follow_system(
+    time: Res<Time>,
+    bounds: Option<Res<LevelBounds>>,
+    player: Query<(Entity, &Transform, &Velocity), (With<Player>, Without<CameraRig>)>,
+    balls: Query<&Transform, (With<Ball>, Without<CameraRig>)>,
+    tethers: Query<&Tether>,
+    mut cameras: Query<(&mut CameraRig, &mut Transform, &mut OrthographicProjection)>,
+) {
+    let Ok((player, player_transform, velocity)) = player.get_single() else { return };
+    let diver = player_transform.translation.truncate();
+    let dt = time.delta_secs();
+
+    // Both ends of a taut rope stay on screen.
+    let taut_ball = tethers.iter()
+        .find(|tether| tether.player == player)
+        .and_then(|tether| Some((tether, balls.get(*tether.links.last()?).ok()?)))
+        .map(|(tether, ball)| (tether, ball.translation.truncate()))
+        .filter(|(tether, ball)| diver.distance(*ball) >= tether.length * TAUT_RATIO)
+        .map(|(_, ball)| ball);
+
+    for (mut rig, mut transform, mut projection) in &mut cameras {
+        if rig.position.is_none() {
+            rig.focus = diver;
+            rig.zoom = 1.0;
+        }
+        let unzoomed = projection.area.size() / projection.scale;
+        let look_ahead = (velocity.linvel * rig.look_ahead).clamp_length_max(rig.max_look_ahead);
+        let (target, zoom) = match taut_ball {
+            Some(ball) => {
+                let needed = ((diver - ball).abs() + FRAMING_MARGIN * 2.0) / unzoomed;
+                ((diver + ball) / 2.0, needed.max_element().clamp(1.0, MAX_ZOOM_OUT))
+            }
+            None => (diver + look_ahead, 1.0),
+        };
+
+        let offset = target - rig.focus;
+        let dead_zone = rig.dead_zone;
+        rig.focus += offset - offset.clamp(-dead_zone, dead_zone);
+
+        let blend = 1.0 - (-rig.smoothing * dt).exp();
+        rig.zoom = rig.zoom.lerp(zoom, blend);
+        let mut position = rig.position.map_or(rig.focus, |position| position.lerp(rig.focus, blend));
+
+        // Keep the view between the shaft walls, centred when the shaft is narrower than the view.
+        if let Some(bounds) = &bounds {
+            let half = unzoomed * rig.zoom / 2.0;
+            position.x = if bounds.max.x - bounds.min.x > half.x * 2.0 {
+                position.x.clamp(bounds.min.x + half.x, bounds.max.x - half.x)
+            } else {
+                (bounds.min.x + bounds.max.x) / 2.0
+            };
+            position.y = position.y.min(bounds.max.y - half.y);
+        }
+        rig.position = Some(position);
+
+        rig.trauma = (rig.trauma - rig.trauma_decay * dt).max(0.0);
+        let shake = rig.trauma * rig.trauma;
+        let t = time.elapsed_secs() * 20.0;
+        let jitter = Vec2::new(
+            simplex_noise_2d_seeded(Vec2::new(t, 0.0), 1.0),
+            simplex_noise_2d_seeded(Vec2::new(t, 0.0), 2.0),
+        );
+
+        transform.translation = (position + jitter * rig.max_shake_offset * shake).extend(transform.translation.z);
+        transform.rotation = Quat::from_rotation_z(rig.max_shake_angle * shake * simplex_noise_2d_seeded(Vec2::new(t, 0.0), 3.0));
+        projection.scale = rig.zoom;
+    }
+}
+
+pub fn camera_trauma_system(
+    players: Query<(), With<Player>>,
+    mut damage: EventReader<DamageEvent>,
+    mut ball_hits: EventReader<BallHitEvent>,
+    mut wall_hits: EventReader<Collided<Ball, Wall>>,
+    mut cameras: Query<&mut CameraRig>,
+) {
+    let mut trauma = 0.0;
+    for event in damage.read() {
+        if players.contains(event.target) { trauma += 0.3 + event.amount / 50.0; }
+    }
+    for event in ball_hits.read() {
+        trauma += (event.speed / 2000.0).min(0.5);
+    }
+    for event in wall_hits.read() {
+        if event.impulse < WALL_IMPACT_THRESHOLD { continue }
+        trauma += (event.impulse / WALL_IMPACT_PER_TRAUMA).min(0.4);
+    }
+    if trauma == 0.0 { return }
+    for mut rig in &mut cameras { rig.add_trauma(trauma); }
+}
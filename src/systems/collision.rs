@@ -0,0 +1,56 @@
+use std::marker::PhantomData;
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CollisionPhase {
+    Started,
+    Stopped,
+}
+
+// A Rapier collision between an entity with `A` and one with `B`, always in that order.
+// Each pair a system cares about gets its own router, see `route_collisions_system`.
+#[derive(Event)]
+pub struct Collided<A: Component, B: Component> {
+    pub a: Entity,
+    pub b: Entity,
+    pub phase: CollisionPhase,
+    // Total contact impulse of the step the pair started touching in, zero for sensors and stops.
+    pub impulse: f32,
+    marker: PhantomData<fn() -> (A, B)>,
+}
+
+impl<A: Component, B: Component> Collided<A, B> {
+    pub fn started(&self) -> bool {
+        self.phase == CollisionPhase::Started
+    }
+}
+
+pub fn route_collisions_system<A: Component, B: Component>(
+    mut collision_events: EventReader<CollisionEvent>,
+    mut routed: EventWriter<Collided<A, B>>,
+    rapier: ReadRapierContext,
+    a: Query<(), With<A>>,
+    b: Query<(), With<B>>,
+) {
+    let context = rapier.single();
+    for event in collision_events.read() {
+        let (e1, e2, phase) = match event {
+            CollisionEvent::Started(e1, e2, _) => (*e1, *e2, CollisionPhase::Started),
+            CollisionEvent::Stopped(e1, e2, _) => (*e1, *e2, CollisionPhase::Stopped),
+        };
+        let (first, second) = if a.contains(e1) && b.contains(e2) {
+            (e1, e2)
+        } else if a.contains(e2) && b.contains(e1) {
+            (e2, e1)
+        } else {
+            continue;
+        };
+
+        let impulse = match phase {
+            CollisionPhase::Started => context.contact_pair(first, second).map_or(0.0, |pair| pair.raw.total_impulse_magnitude()),
+            CollisionPhase::Stopped => 0.0,
+        };
+        routed.send(Collided { a: first, b: second, phase, impulse, marker: PhantomData });
+    }
+}
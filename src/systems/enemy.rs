@@ -1,54 +1,162 @@
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
-use std::collections::HashMap;
+use serde::Deserialize;
 
 use crate::player::*;
 use crate::animation::*;
+use crate::state_machine::*;
+use crate::steering::*;
+use crate::perception::*;
+use crate::health::*;
+use crate::game_state::*;
+use crate::assets::*;
+use crate::collision::*;
+use crate::layers::*;
+use std::f32::consts::FRAC_PI_2;
 
 macro_rules! vec2 { ($x:expr, $y:expr) => { Vec2 { x: $x, y: $y } }; }
 
+// Every fish, whichever prey it hunts.
+#[derive(Component)]
+pub struct Fish;
+
 #[derive(Component)]
 pub struct PlayerFish;
 
 #[derive(Component)]
 pub struct BallFish;
 
-#[derive(Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Eq, Hash, PartialEq)]
 pub enum FishAnimation {
     Idle,
+    // Holds the attack frame while the fish winds up.
+    WindUp,
+    // Plays once through the lunge, the sheet marks the frame the jaws snap shut on.
     Attack,
 }
 
-pub fn spawn_player_fish(mut commands: Commands, asset_server: Res<AssetServer>, mut texture_atlas_layouts: ResMut<Assets<TextureAtlasLayout>>)
-{
+impl AnimationKey for FishAnimation {
+    fn clip_name(&self) -> &'static str {
+        match self {
+            FishAnimation::Idle => "Idle",
+            FishAnimation::WindUp => "WindUp",
+            FishAnimation::Attack => "Attack",
+        }
+    }
+}
+
+const FISH_SIZE: f32 = 150.0;
+const CONTACT_DAMAGE: f32 = 10.0;
+const CONTACT_KNOCKBACK: f32 = 600_000.0;
+// Below this ball speed a hit only nudges the fish.
+const BALL_MIN_HIT_SPEED: f32 = 150.0;
+const BALL_DAMAGE_PER_SPEED: f32 = 0.08;
+const BALL_KNOCKBACK_PER_SPEED: f32 = 3_000.0;
+const BALL_STUN_PER_SPEED: f32 = 0.004;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize)]
+pub enum FishKind {
+    // Hunts the diver.
+    Player,
+    // Goes after the ball.
+    Ball,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum AttackPhase {
+    #[default]
+    Ready,
+    // Holds the attack frame in front of the prey as a telegraph.
+    WindUp,
+    Lunge,
+    // Backs off before it can attack again.
+    Cooldown,
+}
+
+#[derive(Component)]
+pub struct FishAttack {
+    pub range: f32,
+    pub wind_up: f32,
+    pub lunge_impulse: f32,
+    pub lunge_time: f32,
+    pub cooldown: f32,
+    // How the fish backs off during the cooldown.
+    pub retreat: (Behaviour, f32),
+    pub phase: AttackPhase,
+    pub timer: f32,
+    // Set once the lunge reaches the bite frame of the Attack clip, the bite lands on the next contact.
+    pub jaws_closing: bool,
+}
+
+impl FishAttack {
+    pub fn for_kind(kind: FishKind) -> Self {
+        let (range, wind_up, lunge_impulse, lunge_time, cooldown, retreat) = match kind {
+            FishKind::Player => (220.0, 0.35, 1_200_000.0, 0.3, 1.5, (Behaviour::Flee, 0.6)),
+            // Circles the ball instead of swimming off, waiting for another opening.
+            FishKind::Ball => (160.0, 0.5, 800_000.0, 0.25, 2.5, (Behaviour::Orbit { radius: 250.0 }, 0.6)),
+        };
+        Self { range, wind_up, lunge_impulse, lunge_time, cooldown, retreat, phase: AttackPhase::Ready, timer: 0.0, jaws_closing: false }
+    }
+
+    fn enter(&mut self, phase: AttackPhase) {
+        self.phase = phase;
+        self.timer = 0.0;
+        self.jaws_closing = false;
+    }
+}
 
-    let texture = asset_server.load("textures/fish_1.png");
-    let layout = TextureAtlasLayout::from_grid(UVec2 { x: 346, y: 262 }, 5, 2, None, None);
-    let texture_atlas_layout = texture_atlas_layouts.add(layout);
+#[derive(Event)]
+#[allow(dead_code)]
+pub struct BallHitEvent {
+    pub fish: Entity,
+    pub speed: f32,
+    pub damage: f32,
+}
+
+#[derive(Event)]
+pub struct FishBiteEvent {
+    pub fish: Entity,
+    pub target: Entity,
+}
 
-    let mut sprite = Sprite::from_atlas_image(
-        texture,
+pub fn spawn_fish(commands: &mut Commands, textures: &TextureAssets, animations: &AnimationAssets, kind: FishKind, position: Vec2) -> Entity
+{
+    let sprite = Sprite::from_atlas_image(
+        textures.fish.clone(),
         TextureAtlas {
-            layout: texture_atlas_layout,
+            layout: animations.fish_layout.clone(),
             index: 1,
         },
     );
-    sprite.custom_size = Some(vec2!((150.0) * (346.0 / 262.0), 150.0));
-    let mut clips = HashMap::new();
-    clips.insert(FishAnimation::Idle, AnimationSlice { first: 0, last: 1 });
-    clips.insert(FishAnimation::Attack, AnimationSlice { first: 2, last: 2 });
-
-    commands
-        .spawn(RigidBody::Dynamic)
-        .insert(CollisionGroups::new(
-            Group::GROUP_2,
-            Group::GROUP_1 | Group::GROUP_2 | Group::GROUP_4,
-        ))
-        .insert(PlayerFish)
+
+    let mut fish = commands.spawn(RigidBody::Dynamic);
+    match kind {
+        FishKind::Player => fish.insert(PlayerFish).insert(AcquireTarget::<Player>::default()),
+        FishKind::Ball => fish.insert(BallFish).insert(AcquireTarget::<Ball>::default()),
+    };
+    fish
+        .insert(Fish)
+        .insert(StateScoped(InGame))
+        .insert(Layer::Fish.groups())
+        .insert(Steering::new(200_000.0)
+            .behaviour(Behaviour::Wander { frequency: 0.3 }, 0.4)
+            .turn_rate(5.0))
+        .insert(Perception::new(600.0, 120f32.to_radians()))
+        .insert(FishAttack::for_kind(kind))
+        .insert(match kind {
+            FishKind::Player => Health::new(30.0, 0.3),
+            FishKind::Ball => Health::new(50.0, 0.3),
+        })
+        .insert(AwarenessBehaviours {
+            patrol: vec![(Behaviour::Wander { frequency: 0.3 }, 0.4)],
+            alert: vec![(Behaviour::Seek, 0.1)],
+            chase: vec![(Behaviour::Pursue { max_prediction: 0.5 }, 1.0), (Behaviour::Wander { frequency: 0.5 }, 0.3)],
+            lost_track: vec![(Behaviour::Arrive { slowing_radius: 100.0 }, 0.7)],
+        })
         .insert(Velocity::default())
         .insert(Collider::capsule(vec2!(0.0, 0.0), vec2!(0.0, 30.0), 30.0))
         .insert(ActiveEvents::COLLISION_EVENTS)
-        .insert(Transform::from_xyz(300.0, 200.0, 0.0))
+        .insert(Transform::from_translation(position.extend(0.0)))
         .insert(ExternalForce::default())
         .insert(ExternalImpulse::default())
         .insert(Damping {
@@ -58,100 +166,207 @@ pub fn spawn_player_fish(mut commands: Commands, asset_server: Res<AssetServer>,
         .with_children(|parent| {
             parent
                 .spawn(sprite)
-                .insert(Animator {
-                    current: FishAnimation::Idle,
-                    timer: Timer::from_seconds(0.6, TimerMode::Repeating),
-                    clips: clips,
-                })
-                .insert(Transform::from_xyz(0.0, 0.0, 0.0).with_rotation(Quat::from_euler(EulerRot::XYZ, 0.0, 0.0, -3.1415926 / 2.0)));
-        });
-        
+                .insert(Animator::from_sheet(
+                    animations.fish.clone(),
+                    FishAnimation::Idle,
+                ).with_height(FISH_SIZE))
+                .insert(AnimationStateMachine::new(FishAnimation::Idle)
+                    .transition(Transition::to(FishAnimation::WindUp).when(AnimationCondition::Trigger("wind_up")))
+                    .transition(Transition::to(FishAnimation::Attack)
+                        .from(FishAnimation::WindUp)
+                        .when(AnimationCondition::Trigger("attack")))
+                    .transition(Transition::to(FishAnimation::Idle)
+                        .from(FishAnimation::Attack)
+                        .when(AnimationCondition::Finished))
+                    // The lunge can end on a bite before the clip does.
+                    .transition(Transition::to(FishAnimation::Idle).when(AnimationCondition::Trigger("recover")))
+                    // Flinches with its jaws open when the ball hits it.
+                    .transition(Transition::to(FishAnimation::Idle)
+                        .via(FishAnimation::WindUp)
+                        .when(AnimationCondition::Trigger("hit"))
+                        .priority(1)))
+                .insert(Transform::from_xyz(0.0, 0.0, 0.0).with_rotation(Quat::from_euler(EulerRot::XYZ, 0.0, 0.0, -FRAC_PI_2)));
+        })
+        .id()
+}
+
+fn trigger_animation(children: &Children, params: &mut Query<&mut AnimationParams>, name: &'static str) {
+    for child in children.iter() {
+        if let Ok(mut params) = params.get_mut(*child) { params.trigger(name) }
+    }
 }
 
-pub fn fish_follow_player_system(
-    player_query: Query<&Transform, With<Player>>,
-    mut enemy_query: Query<(&mut Transform, &mut ExternalForce), (With<PlayerFish>, Without<Player>)>,
+pub fn fish_attack_system(
     time: Res<Time>,
+    rapier: ReadRapierContext,
+    mut bites: EventWriter<FishBiteEvent>,
+    transforms: Query<&GlobalTransform>,
+    mut params: Query<&mut AnimationParams>,
+    mut fish: Query<(Entity, &mut FishAttack, &Perception, &mut Steering, &mut ExternalImpulse, &Children, Option<&AwarenessBehaviours>), Without<Stunned>>,
 ) {
-    if let Ok(player_transform) = player_query.get_single() {
-        let player_pos = player_transform.translation.truncate();
+    let context = rapier.single();
+    for (entity, mut attack, perception, mut steering, mut impulse, children, behaviours) in &mut fish {
+        attack.timer += time.delta_secs();
+        let Some(prey) = perception.prey else { continue };
+        let (Ok(own), Ok(target)) = (transforms.get(entity), transforms.get(prey)) else { continue };
+        let offset = target.translation().truncate() - own.translation().truncate();
 
-        for (mut enemy_transform, mut force) in &mut enemy_query {
-            let enemy_pos = enemy_transform.translation.truncate();
-            let direction = (player_pos - enemy_pos).normalize_or_zero();
+        match attack.phase {
+            AttackPhase::Ready => {
+                if perception.state != Awareness::Chase || offset.length() > attack.range { continue }
+                attack.enter(AttackPhase::WindUp);
+                steering.behaviours = vec![(Behaviour::Seek, 0.05)];
+                trigger_animation(children, &mut params, "wind_up");
+            }
+            AttackPhase::WindUp => {
+                if attack.timer < attack.wind_up { continue }
+                attack.enter(AttackPhase::Lunge);
+                steering.behaviours.clear();
+                impulse.impulse = offset.normalize_or_zero() * attack.lunge_impulse;
+                trigger_animation(children, &mut params, "attack");
+            }
+            AttackPhase::Lunge => {
+                let bitten = attack.jaws_closing && context.contact_pair(entity, prey).is_some_and(|pair| pair.has_any_active_contact());
+                if bitten { bites.send(FishBiteEvent { fish: entity, target: prey }); }
+                if !bitten && attack.timer < attack.lunge_time { continue }
+                attack.enter(AttackPhase::Cooldown);
+                steering.behaviours = vec![attack.retreat];
+                trigger_animation(children, &mut params, "recover");
+            }
+            AttackPhase::Cooldown => {
+                if attack.timer < attack.cooldown { continue }
+                attack.enter(AttackPhase::Ready);
+                if let Some(behaviours) = behaviours { steering.behaviours = behaviours.get(perception.state).to_vec() }
+            }
+        }
+    }
+}
 
-            let target_angle = direction.y.atan2(direction.x) - 3.1415926 / 2.0;
-            let target_rotation = Quat::from_rotation_z(target_angle);
-            let rotation_speed = 5.0;
-            enemy_transform.rotation = enemy_transform.rotation.slerp(target_rotation, rotation_speed * time.delta_secs());
+// The sprite is a child of the fish, so its "bite" marker arms the parent's jaws.
+pub fn fish_bite_frame_system(
+    mut markers: EventReader<AnimationMarker<FishAnimation>>,
+    sprites: Query<&Parent>,
+    mut fish: Query<&mut FishAttack>,
+) {
+    for marker in markers.read().filter(|marker| marker.clip == FishAnimation::Attack && marker.name == "bite") {
+        let Ok(parent) = sprites.get(marker.entity) else { continue };
+        let Ok(mut attack) = fish.get_mut(parent.get()) else { continue };
+        if attack.phase == AttackPhase::Lunge { attack.jaws_closing = true }
+    }
+}
 
-            let strength = 200_000.0;
-            force.force = direction * strength;
-        }
+pub fn detect_playerfish_collision_system(
+    mut contacts: EventReader<Collided<PlayerFish, Player>>,
+    mut damage: EventWriter<DamageEvent>,
+    transforms: Query<&GlobalTransform>,
+    fish: Query<&FishAttack>,
+) {
+    for contact in contacts.read().filter(|contact| contact.started()) {
+        // A lunging fish hurts with its bite instead.
+        if fish.get(contact.a).is_ok_and(|attack| attack.phase == AttackPhase::Lunge) { continue }
+        damage.send(DamageEvent {
+            target: contact.b,
+            amount: CONTACT_DAMAGE,
+            knockback: knockback(&transforms, contact.a, contact.b, CONTACT_KNOCKBACK),
+        });
     }
 }
 
-pub fn fish_follow_ball_system(
-    player_query: Query<&Transform, With<Ball>>,
-    mut enemy_query: Query<(&mut Transform, &mut ExternalForce), (With<BallFish>, Without<Ball>)>,
-    time: Res<Time>,
+pub fn detect_ballfish_collision_system(
+    mut contacts: EventReader<Collided<Ball, Fish>>,
+    mut damage: EventWriter<DamageEvent>,
+    mut hits: EventWriter<BallHitEvent>,
+    mut commands: Commands,
+    transforms: Query<&GlobalTransform>,
+    mut params: Query<&mut AnimationParams>,
+    mut fish_query: Query<(Option<&mut FishAttack>, &Children), With<Fish>>,
+    ball: Query<&Velocity, With<Ball>>,
 ) {
-    if let Ok(ball_transform) = player_query.get_single() {
-        let player_pos = ball_transform.translation.truncate();
+    for contact in contacts.read().filter(|contact| contact.started()) {
+        let (ball_entity, fish) = (contact.a, contact.b);
+        let (Ok((attack, children)), Ok(velocity)) = (fish_query.get_mut(fish), ball.get(ball_entity)) else { continue };
 
-        for (mut fish_transform, mut force) in &mut enemy_query {
-            let enemy_pos = fish_transform.translation.truncate();
-            let direction = (player_pos - enemy_pos).normalize_or_zero();
+        // The ball barely slows down against a fish, so its own speed is the impact speed.
+        let speed = velocity.linvel.length();
+        if speed < BALL_MIN_HIT_SPEED { continue }
+        let amount = (speed - BALL_MIN_HIT_SPEED) * BALL_DAMAGE_PER_SPEED;
 
-            let target_angle = direction.y.atan2(direction.x) - 3.1415926 / 2.0;
-            let target_rotation = Quat::from_rotation_z(target_angle);
-            let rotation_speed = 5.0;
-            fish_transform.rotation = fish_transform.rotation.slerp(target_rotation, rotation_speed * time.delta_secs());
+        damage.send(DamageEvent {
+            target: fish,
+            amount,
+            knockback: knockback(&transforms, ball_entity, fish, speed * BALL_KNOCKBACK_PER_SPEED),
+        });
+        hits.send(BallHitEvent { fish, speed, damage: amount });
 
-            let strength = 200_000.0;
-            force.force = direction * strength;
-        }
+        commands.entity(fish).try_insert(Stunned(BALL_STUN_PER_SPEED * speed));
+        if let Some(mut attack) = attack { attack.enter(AttackPhase::Cooldown) }
+        trigger_animation(children, &mut params, "hit");
     }
 }
 
-pub fn detect_playerfish_collision_system(
-    mut collision_events: EventReader<CollisionEvent>,
-    fish_query: Query<Entity, With<PlayerFish>>,
-    player_query: Query<Entity, With<Player>>,
+pub fn stunned_fish_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut fish: Query<(Entity, &mut Stunned, &mut ExternalForce)>,
 ) {
-    for event in collision_events.read() {
-        match event {
-            CollisionEvent::Started(e1, e2, _flags) => {
-                let is_fish = fish_query.get(*e1).is_ok() || fish_query.get(*e2).is_ok();
-                let is_player = player_query.get(*e1).is_ok() || player_query.get(*e2).is_ok();
-
-                if is_fish && is_player {
-                    println!("💥 PlayerFish and Player just collided!");
-                    // ici tu peux déclencher une action (dégâts, repoussement, etc.)
-                }
-            }
-            _ => {}
-        }
+    for (entity, mut stunned, mut force) in &mut fish {
+        force.force = Vec2::ZERO;
+        stunned.0 -= time.delta_secs();
+        if stunned.0 <= 0.0 { commands.entity(entity).remove::<Stunned>(); }
     }
 }
 
-pub fn detect_ballfish_collision_system(
-    mut collision_events: EventReader<CollisionEvent>,
-    fish_query: Query<Entity, With<BallFish>>,
-    ball: Query<Entity, With<Ball>>,
+pub fn fish_death_system(
+    mut commands: Commands,
+    mut deaths: EventReader<DeathEvent>,
+    fish: Query<(), With<Fish>>,
 ) {
-    for event in collision_events.read() {
-        match event {
-            CollisionEvent::Started(e1, e2, _flags) => {
-                let is_fish = fish_query.get(*e1).is_ok() || fish_query.get(*e2).is_ok();
-                let is_ball = ball.get(*e1).is_ok() || ball.get(*e2).is_ok();
-
-                if is_fish && is_ball {
-                    println!("💥 BallFish and Ball just collided!");
-                    // ici tu peux déclencher une action (dégâts, repoussement, etc.)
-                }
-            }
-            _ => {}
-        }
+    for death in deaths.read() {
+        if fish.contains(death.entity) { commands.entity(death.entity).despawn_recursive(); }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    fn fish_in_range(world: &mut World, stunned: bool) -> Entity {
+        let prey = world.spawn(GlobalTransform::from_xyz(100.0, 0.0, 0.0)).id();
+        let mut perception = Perception::new(600.0, 120f32.to_radians());
+        perception.prey = Some(prey);
+        perception.state = Awareness::Chase;
+
+        let sprite = world.spawn(AnimationParams::default()).id();
+        let mut fish = world.spawn((
+            FishAttack::for_kind(FishKind::Player),
+            perception,
+            Steering::new(200_000.0),
+            ExternalImpulse::default(),
+            GlobalTransform::default(),
+        ));
+        fish.add_child(sprite);
+        if stunned { fish.insert(Stunned(1.0)); }
+        fish.id()
+    }
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.init_resource::<Time>();
+        world.init_resource::<Events<FishBiteEvent>>();
+        world.spawn((DefaultRapierContext, RapierContextSimulation::default()));
+        world
+    }
+
+    #[test]
+    fn stunned_fish_does_not_wind_up() {
+        let mut world = world();
+        let stunned = fish_in_range(&mut world, true);
+        let alert = fish_in_range(&mut world, false);
+
+        world.run_system_once(fish_attack_system).unwrap();
+
+        assert_eq!(world.get::<FishAttack>(stunned).unwrap().phase, AttackPhase::Ready);
+        assert_eq!(world.get::<FishAttack>(alert).unwrap().phase, AttackPhase::WindUp);
+    }
+}
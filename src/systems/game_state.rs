@@ -0,0 +1,100 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use crate::player::*;
+use crate::hover::*;
+use crate::health::*;
+use crate::spawner::*;
+use crate::input::*;
+
+// Seconds between the diver dying and the game over screen.
+const GAME_OVER_DELAY: f32 = 1.5;
+
+#[derive(States, Default, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum GameState {
+    // Sets up what every screen shares, then moves on to Loading.
+    #[default]
+    Boot,
+    Loading,
+    MainMenu,
+    Playing,
+    Paused,
+    GameOver,
+    // An asset collection failed to load, the asset server logs which file.
+    LoadingFailed,
+}
+
+// Active while a run is going, paused or not. Scene entities are scoped to it.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct InGame;
+
+impl ComputedStates for InGame {
+    type SourceStates = GameState;
+
+    fn compute(sources: GameState) -> Option<Self> {
+        matches!(sources, GameState::Playing | GameState::Paused).then_some(InGame)
+    }
+}
+
+pub fn finish_boot(mut next_state: ResMut<NextState<GameState>>) {
+    next_state.set(GameState::Loading);
+}
+
+pub fn reset_run(
+    mut drag_state: ResMut<DragState>,
+    mut hover: ResMut<HoverState>,
+    mut fling: ResMut<GamepadFling>,
+    mut joystick: ResMut<VirtualJoystick>,
+    mut spawner: ResMut<Spawner>,
+) {
+    *drag_state = DragState::default();
+    *hover = HoverState::default();
+    *fling = GamepadFling::default();
+    *joystick = VirtualJoystick::default();
+    *spawner = Spawner::default();
+}
+
+pub fn main_menu_input_system(keys: Res<ButtonInput<KeyCode>>, mut next_state: ResMut<NextState<GameState>>) {
+    if keys.any_just_pressed([KeyCode::Enter, KeyCode::Space]) { next_state.set(GameState::Playing) }
+}
+
+pub fn pause_input_system(
+    actions: PlayerActions,
+    rebinding: Res<Rebinding>,
+    state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if !actions.started::<Pause>() || rebinding.0.is_some() { return }
+    match state.get() {
+        GameState::Playing => next_state.set(GameState::Paused),
+        GameState::Paused => next_state.set(GameState::Playing),
+        _ => {}
+    }
+}
+
+pub fn game_over_input_system(keys: Res<ButtonInput<KeyCode>>, mut next_state: ResMut<NextState<GameState>>) {
+    if keys.any_just_pressed([KeyCode::KeyR, KeyCode::Enter]) { next_state.set(GameState::Playing) }
+    if keys.just_pressed(KeyCode::Escape) { next_state.set(GameState::MainMenu) }
+}
+
+pub fn pause_physics(mut config: Query<&mut RapierConfiguration>) {
+    for mut config in &mut config { config.physics_pipeline_active = false }
+}
+
+pub fn resume_physics(mut config: Query<&mut RapierConfiguration>) {
+    for mut config in &mut config { config.physics_pipeline_active = true }
+}
+
+pub fn game_over_on_death_system(
+    time: Res<Time>,
+    mut deaths: EventReader<DeathEvent>,
+    players: Query<(), With<Player>>,
+    mut countdown: Local<Option<f32>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if deaths.read().any(|death| players.contains(death.entity)) { *countdown = Some(GAME_OVER_DELAY) }
+    let Some(left) = countdown.as_mut() else { return };
+    *left -= time.delta_secs();
+    if *left > 0.0 { return }
+    *countdown = None;
+    next_state.set(GameState::GameOver);
+}
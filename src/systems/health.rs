@@ -0,0 +1,121 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use crate::player::*;
+use crate::enemy::*;
+use crate::state_machine::*;
+
+const BITE_DAMAGE: f32 = 25.0;
+const BITE_KNOCKBACK: f32 = 1_500_000.0;
+// Blinks per second while invulnerable.
+const FLASH_RATE: f32 = 12.0;
+
+#[derive(Component)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+    pub invulnerable_time: f32,
+    // Time left before damage is taken again.
+    pub invulnerable: f32,
+}
+
+impl Health {
+    pub fn new(max: f32, invulnerable_time: f32) -> Self {
+        Self { current: max, max, invulnerable_time, invulnerable: 0.0 }
+    }
+}
+
+// Stops input and further damage.
+#[derive(Component)]
+pub struct Dead;
+
+#[derive(Event)]
+pub struct DamageEvent {
+    pub target: Entity,
+    pub amount: f32,
+    // Impulse applied to the target if the hit lands.
+    pub knockback: Vec2,
+}
+
+#[derive(Event)]
+pub struct DeathEvent {
+    pub entity: Entity,
+}
+
+pub fn knockback(transforms: &Query<&GlobalTransform>, from: Entity, to: Entity, strength: f32) -> Vec2 {
+    let (Ok(from), Ok(to)) = (transforms.get(from), transforms.get(to)) else { return Vec2::ZERO };
+    (to.translation() - from.translation()).truncate().normalize_or_zero() * strength
+}
+
+pub fn fish_bite_damage_system(
+    mut bites: EventReader<FishBiteEvent>,
+    mut damage: EventWriter<DamageEvent>,
+    transforms: Query<&GlobalTransform>,
+) {
+    for bite in bites.read() {
+        damage.send(DamageEvent {
+            target: bite.target,
+            amount: BITE_DAMAGE,
+            knockback: knockback(&transforms, bite.fish, bite.target, BITE_KNOCKBACK),
+        });
+    }
+}
+
+pub fn apply_damage_system(
+    mut commands: Commands,
+    mut events: EventReader<DamageEvent>,
+    mut deaths: EventWriter<DeathEvent>,
+    mut targets: Query<(&mut Health, Option<&mut ExternalImpulse>), Without<Dead>>,
+) {
+    // Hits landing on the same target in the same frame don't stack: only the strongest one
+    // counts, whatever order they were sent in.
+    let mut hits: Vec<&DamageEvent> = Vec::new();
+    for event in events.read() {
+        match hits.iter_mut().find(|hit| hit.target == event.target) {
+            Some(hit) if hit.amount >= event.amount => {}
+            Some(hit) => *hit = event,
+            None => hits.push(event),
+        }
+    }
+
+    for event in hits {
+        let Ok((mut health, impulse)) = targets.get_mut(event.target) else { continue };
+        if health.invulnerable > 0.0 { continue }
+
+        health.current = (health.current - event.amount).max(0.0);
+        health.invulnerable = health.invulnerable_time;
+        if let Some(mut impulse) = impulse { impulse.impulse += event.knockback }
+
+        if health.current <= 0.0 {
+            commands.entity(event.target).try_insert(Dead);
+            deaths.send(DeathEvent { entity: event.target });
+        }
+    }
+}
+
+// Fish carry their sprite on a child, so both the entity and its children blink.
+pub fn invulnerability_flash_system(
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut Health, Option<&Children>)>,
+    mut sprites: Query<&mut Sprite>,
+) {
+    for (entity, mut health, children) in &mut query {
+        // Left untouched otherwise, so `Changed<Health>` still means something.
+        if health.invulnerable <= 0.0 { continue }
+        health.invulnerable = (health.invulnerable - time.delta_secs()).max(0.0);
+        let hidden = health.invulnerable > 0.0 && (health.invulnerable * FLASH_RATE).fract() < 0.5;
+        for target in std::iter::once(entity).chain(children.into_iter().flatten().copied()) {
+            if let Ok(mut sprite) = sprites.get_mut(target) { sprite.color.set_alpha(if hidden { 0.3 } else { 1.0 }) }
+        }
+    }
+}
+
+pub fn player_death_system(
+    mut deaths: EventReader<DeathEvent>,
+    mut players: Query<(&mut ExternalForce, &mut AnimationParams), With<Player>>,
+) {
+    for death in deaths.read() {
+        let Ok((mut force, mut params)) = players.get_mut(death.entity) else { continue };
+        force.force = Vec2::ZERO;
+        params.intent = Vec2::ZERO;
+    }
+}
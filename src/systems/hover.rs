@@ -1,13 +1,26 @@
 use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use crate::input::*;
+use crate::player::*;
 
 // HOVER 
 
 #[derive(Component)]
 pub struct Hoverable;
 
+// Top-most hoverable under the cursor.
+#[derive(Resource, Default)]
+pub struct HoverState {
+    pub entity: Option<Entity>,
+}
+
+#[derive(Event)]
+pub struct HoverEnterEvent {
+    pub entity: Entity,
+}
+
 #[derive(Event)]
-pub struct HoveredEvent
-{
+pub struct HoverExitEvent {
     pub entity: Entity,
 }
 
@@ -18,6 +31,8 @@ pub struct Draggable;
 pub struct DragState {
     pub active_entity: Option<Entity>,
     pub drag_start: Option<Vec2>,
+    // Finger holding the drag, `None` for the mouse.
+    pub touch: Option<u64>,
 }
 
 #[derive(Event)]
@@ -40,58 +55,145 @@ fn cursor_world_position(
     })
 }
     
+// Picks against colliders so rotation and shape count, the highest z wins.
+fn pick(context: &RapierContext, point: Vec2, hoverables: &Query<&GlobalTransform, With<Hoverable>>) -> Option<Entity> {
+    let mut best: Option<(Entity, f32)> = None;
+    let predicate = |entity| hoverables.contains(entity);
+    context.intersections_with_point(point, QueryFilter::new().predicate(&predicate), |entity| {
+        let z = hoverables.get(entity).map_or(f32::MIN, |transform| transform.translation().z);
+        if best.is_none_or(|(_, best_z)| z > best_z) { best = Some((entity, z)) }
+        true
+    });
+    best.map(|(entity, _)| entity)
+}
+
 pub fn check_hover_system(
     windows: Query<&Window>,
     camera_q: Query<(&Camera, &GlobalTransform)>,
-    mut events: EventWriter<HoveredEvent>,
-    query: Query<(&GlobalTransform, &Sprite, Entity), With<Hoverable>>,
+    rapier: ReadRapierContext,
+    mut hover: ResMut<HoverState>,
+    mut enter_events: EventWriter<HoverEnterEvent>,
+    mut exit_events: EventWriter<HoverExitEvent>,
+    hoverables: Query<&GlobalTransform, With<Hoverable>>,
+) {
+    let hovered = cursor_world_position(windows, camera_q).and_then(|cursor| pick(&rapier.single(), cursor, &hoverables));
+    if hovered == hover.entity { return }
+    if let Some(entity) = hover.entity { exit_events.send(HoverExitEvent { entity }); }
+    if let Some(entity) = hovered { enter_events.send(HoverEnterEvent { entity }); }
+    hover.entity = hovered;
+}
+
+pub fn hover_highlight_system(
+    mut enter_events: EventReader<HoverEnterEvent>,
+    mut exit_events: EventReader<HoverExitEvent>,
+    mut sprites: Query<&mut Sprite>,
 ) {
-    let Some(cursor_world) = cursor_world_position(windows, camera_q) else { return };
-    for (transform, sprite, entity) in &query {
-        let scale = transform.scale().truncate();
-        let size = sprite.custom_size.unwrap_or(Vec2::ONE) * scale;
-        let position = transform.translation().truncate();
-
-        let half_size = size / 2.0;
-        let min = position - half_size;
-        let max = position + half_size;
-
-        let skip = !(min.x..=max.x).contains(&cursor_world.x) || !(min.y..=max.y).contains(&cursor_world.y);
-        if skip { continue }
-        events.send(HoveredEvent { entity });
+    for HoverExitEvent { entity } in exit_events.read() {
+        if let Ok(mut sprite) = sprites.get_mut(*entity) { sprite.color = Color::WHITE }
+    }
+    for HoverEnterEvent { entity } in enter_events.read() {
+        if let Ok(mut sprite) = sprites.get_mut(*entity) { sprite.color = Color::srgb(0.8, 0.9, 1.0) }
     }
 }
 
 // DRAG 
 
 pub fn click_start_drag_system(
-    buttons: Res<ButtonInput<MouseButton>>,
+    actions: PlayerActions,
     mut drag_state: ResMut<DragState>,
-    mut events: EventReader<HoveredEvent>,
+    hover: Res<HoverState>,
     camera_q: Query<(&Camera, &GlobalTransform)>,
     windows: Query<&Window>,
     query: Query<Entity, With<Draggable>>,
 ) {
-    if !buttons.just_pressed(MouseButton::Left) { return }
+    if !actions.started::<Fling>() || drag_state.active_entity.is_some() { return }
+    let Some(entity) = hover.entity.filter(|entity| query.contains(*entity)) else { return };
     let Some(cursor_pos) = cursor_world_position(windows, camera_q) else { return };
-    for HoveredEvent { entity } in events.read() {
-        if !query.get(*entity).is_ok() { continue }
-        drag_state.active_entity = Some(*entity);
-        drag_state.drag_start = Some(cursor_pos);
-    }
+    drag_state.active_entity = Some(entity);
+    drag_state.drag_start = Some(cursor_pos);
 }
 
 pub fn click_end_drag_system(
-    buttons: Res<ButtonInput<MouseButton>>,
+    actions: PlayerActions,
     windows: Query<&Window>,
     camera_q: Query<(&Camera, &GlobalTransform)>,
     mut drag_state: ResMut<DragState>,
     mut drag_ended_writer: EventWriter<DragEndedEvent>,
 ) {
-    if !buttons.just_released(MouseButton::Left) { return }
+    if !actions.completed::<Fling>() || drag_state.touch.is_some() { return }
     let Some(entity) = drag_state.active_entity.take() else { return };
     let Some(start_pos) = drag_state.drag_start.take() else { return };
     let Some(end_pos) = cursor_world_position(windows, camera_q) else { return };
     let delta = end_pos - start_pos;
     drag_ended_writer.send(DragEndedEvent { entity, delta });
-}
\ No newline at end of file
+}
+
+// TOUCH
+
+pub fn touch_drag_system(
+    touches: Res<Touches>,
+    camera_q: Query<(&Camera, &GlobalTransform)>,
+    mut drag_state: ResMut<DragState>,
+    mut drag_ended_writer: EventWriter<DragEndedEvent>,
+    rapier: ReadRapierContext,
+    hoverables: Query<&GlobalTransform, With<Hoverable>>,
+    query: Query<Entity, With<Draggable>>,
+) {
+    let Ok((camera, camera_transform)) = camera_q.get_single() else { return };
+    let to_world = |position: Vec2| camera.viewport_to_world_2d(camera_transform, position).ok();
+
+    for touch in touches.iter_just_pressed() {
+        if drag_state.active_entity.is_some() { break }
+        let Some(world) = to_world(touch.position()) else { continue };
+        let Some(entity) = pick(&rapier.single(), world, &hoverables).filter(|entity| query.contains(*entity)) else { continue };
+        drag_state.active_entity = Some(entity);
+        drag_state.drag_start = Some(world);
+        drag_state.touch = Some(touch.id());
+    }
+
+    for touch in touches.iter_just_canceled() {
+        if drag_state.touch == Some(touch.id()) { *drag_state = DragState::default() }
+    }
+    for touch in touches.iter_just_released() {
+        if drag_state.touch != Some(touch.id()) { continue }
+        let DragState { active_entity: Some(entity), drag_start: Some(start_pos), .. } = std::mem::take(&mut *drag_state) else { continue };
+        let Some(end_pos) = to_world(touch.position()) else { continue };
+        drag_ended_writer.send(DragEndedEvent { entity, delta: end_pos - start_pos });
+    }
+}
+
+// GAMEPAD FLING
+
+// Seconds of full trigger pressure for a full charge.
+const GAMEPAD_CHARGE_TIME: f32 = 1.0;
+
+#[derive(Resource, Default)]
+pub struct GamepadFling {
+    pub charge: f32,
+    pub aim: Vec2,
+}
+
+pub fn gamepad_fling_system(
+    time: Res<Time>,
+    actions: PlayerActions,
+    mut fling: ResMut<GamepadFling>,
+    mut drag_ended_writer: EventWriter<DragEndedEvent>,
+    query: Query<Entity, With<Draggable>>,
+) {
+    // The stick snaps back when let go, so keep the last real aim.
+    let aim = actions.value::<Aim>().as_axis2d();
+    if aim.length() > 0.5 { fling.aim = aim.normalize() }
+
+    let pressure = actions.value::<Charge>().as_axis1d();
+    if pressure > 0.05 {
+        fling.charge = (fling.charge + pressure * time.delta_secs() / GAMEPAD_CHARGE_TIME).min(1.0);
+        return;
+    }
+    if fling.charge <= 0.0 { return }
+    let (charge, aim) = (fling.charge, fling.aim);
+    *fling = GamepadFling::default();
+    if aim == Vec2::ZERO { return }
+    let Ok(entity) = query.get_single() else { return };
+    // A mouse drag pulls away from where the ball should go.
+    drag_ended_writer.send(DragEndedEvent { entity, delta: -aim * charge * MAX_PULL });
+}
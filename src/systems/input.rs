@@ -0,0 +1,326 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::ErrorKind;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+use bevy::prelude::*;
+use bevy::ecs::system::SystemParam;
+use bevy_enhanced_input::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::player::*;
+use crate::hover::*;
+
+#[cfg(not(target_arch = "wasm32"))]
+const BINDINGS_PATH: &str = "config/input.ron";
+
+#[derive(Debug, InputAction)]
+#[input_action(output = Vec2)]
+pub struct Swim;
+
+#[derive(Debug, InputAction)]
+#[input_action(output = bool)]
+pub struct Fling;
+
+#[derive(Debug, InputAction)]
+#[input_action(output = bool)]
+pub struct Pause;
+
+// Direction the ball gets flung towards with a gamepad.
+#[derive(Debug, InputAction)]
+#[input_action(output = Vec2)]
+pub struct Aim;
+
+// Analog pressure charging a gamepad fling, released to throw.
+#[derive(Debug, InputAction)]
+#[input_action(output = f32)]
+pub struct Charge;
+
+#[derive(Debug, InputAction)]
+#[input_action(output = bool)]
+pub struct ReelIn;
+
+#[derive(Debug, InputAction)]
+#[input_action(output = bool)]
+pub struct ReelOut;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Stick {
+    Left,
+    Right,
+}
+
+impl Stick {
+    fn preset(self) -> GamepadStick {
+        match self {
+            Stick::Left => GamepadStick::Left,
+            Stick::Right => GamepadStick::Right,
+        }
+    }
+}
+
+// What each action is bound to. Saved to `BINDINGS_PATH` whenever a binding changes.
+#[derive(Resource, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputBindings {
+    pub up: Vec<Input>,
+    pub down: Vec<Input>,
+    pub left: Vec<Input>,
+    pub right: Vec<Input>,
+    pub stick: Option<Stick>,
+    pub fling: Vec<Input>,
+    pub aim_stick: Option<Stick>,
+    pub charge: Vec<Input>,
+    pub reel_in: Vec<Input>,
+    pub reel_out: Vec<Input>,
+    pub pause: Vec<Input>,
+}
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        Self {
+            up: vec![KeyCode::KeyW.into(), KeyCode::ArrowUp.into()],
+            down: vec![KeyCode::KeyS.into(), KeyCode::ArrowDown.into()],
+            left: vec![KeyCode::KeyA.into(), KeyCode::ArrowLeft.into()],
+            right: vec![KeyCode::KeyD.into(), KeyCode::ArrowRight.into()],
+            stick: Some(Stick::Left),
+            fling: vec![MouseButton::Left.into()],
+            aim_stick: Some(Stick::Right),
+            charge: vec![GamepadButton::RightTrigger2.into()],
+            reel_in: vec![KeyCode::KeyQ.into(), GamepadButton::LeftTrigger.into()],
+            reel_out: vec![KeyCode::KeyE.into(), GamepadButton::RightTrigger.into()],
+            pause: vec![KeyCode::Escape.into(), GamepadButton::Start.into()],
+        }
+    }
+}
+
+impl InputBindings {
+    // Falls back to the defaults when there is no config file yet.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load() -> Self {
+        match fs::read_to_string(BINDINGS_PATH) {
+            Ok(text) => ron::from_str(&text).unwrap_or_else(|error| {
+                warn!("ignoring {BINDINGS_PATH}: {error}");
+                Self::default()
+            }),
+            Err(error) if error.kind() == ErrorKind::NotFound => Self::default(),
+            Err(error) => {
+                warn!("could not read {BINDINGS_PATH}: {error}");
+                Self::default()
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self) {
+        let result = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|error| error.to_string())
+            .and_then(|text| {
+                if let Some(dir) = Path::new(BINDINGS_PATH).parent() { fs::create_dir_all(dir).map_err(|error| error.to_string())? }
+                fs::write(BINDINGS_PATH, text).map_err(|error| error.to_string())
+            });
+        if let Err(error) = result { warn!("could not save {BINDINGS_PATH}: {error}") }
+    }
+
+    // The web build has no filesystem, so rebinding only lasts until the page is closed.
+    #[cfg(target_arch = "wasm32")]
+    pub fn load() -> Self {
+        Self::default()
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn save(&self) {}
+
+    pub fn slot(&self, slot: BindingSlot) -> &[Input] {
+        match slot {
+            BindingSlot::Up => &self.up,
+            BindingSlot::Down => &self.down,
+            BindingSlot::Left => &self.left,
+            BindingSlot::Right => &self.right,
+            BindingSlot::Fling => &self.fling,
+            BindingSlot::Charge => &self.charge,
+            BindingSlot::ReelIn => &self.reel_in,
+            BindingSlot::ReelOut => &self.reel_out,
+            BindingSlot::Pause => &self.pause,
+        }
+    }
+
+    pub fn slot_mut(&mut self, slot: BindingSlot) -> &mut Vec<Input> {
+        match slot {
+            BindingSlot::Up => &mut self.up,
+            BindingSlot::Down => &mut self.down,
+            BindingSlot::Left => &mut self.left,
+            BindingSlot::Right => &mut self.right,
+            BindingSlot::Fling => &mut self.fling,
+            BindingSlot::Charge => &mut self.charge,
+            BindingSlot::ReelIn => &mut self.reel_in,
+            BindingSlot::ReelOut => &mut self.reel_out,
+            BindingSlot::Pause => &mut self.pause,
+        }
+    }
+}
+
+// The rebindable entries, in the order they are listed in the pause menu.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BindingSlot {
+    Up,
+    Down,
+    Left,
+    Right,
+    Fling,
+    Charge,
+    ReelIn,
+    ReelOut,
+    Pause,
+}
+
+impl BindingSlot {
+    pub const ALL: [BindingSlot; 9] = [
+        BindingSlot::Up, BindingSlot::Down, BindingSlot::Left, BindingSlot::Right,
+        BindingSlot::Fling, BindingSlot::Charge, BindingSlot::ReelIn, BindingSlot::ReelOut, BindingSlot::Pause,
+    ];
+
+    // Slots read as a 0..1 value rather than on/off.
+    pub fn is_analog(self) -> bool {
+        self == BindingSlot::Charge
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BindingSlot::Up => "Swim up",
+            BindingSlot::Down => "Swim down",
+            BindingSlot::Left => "Swim left",
+            BindingSlot::Right => "Swim right",
+            BindingSlot::Fling => "Fling",
+            BindingSlot::Charge => "Charge fling",
+            BindingSlot::ReelIn => "Reel in",
+            BindingSlot::ReelOut => "Reel out",
+            BindingSlot::Pause => "Pause",
+        }
+    }
+}
+
+const ANALOG_BUTTONS: [GamepadButton; 2] = [GamepadButton::LeftTrigger2, GamepadButton::RightTrigger2];
+
+// Slot waiting for the next key, mouse or gamepad button to replace its first binding.
+#[derive(Resource, Default)]
+pub struct Rebinding(pub Option<BindingSlot>);
+
+pub fn bind_player_input(mut trigger: Trigger<Binding<Player>>, bindings: Res<InputBindings>) {
+    trigger.bind::<Swim>()
+        .to(Cardinal { north: &bindings.up, east: &bindings.right, south: &bindings.down, west: &bindings.left })
+        .with_modifiers(DeadZone::default());
+    if let Some(stick) = bindings.stick { trigger.bind::<Swim>().to(stick.preset()); }
+    trigger.bind::<Fling>().to(&bindings.fling);
+    if let Some(stick) = bindings.aim_stick { trigger.bind::<Aim>().to(stick.preset()).with_modifiers(DeadZone::default()); }
+    trigger.bind::<Charge>().to(&bindings.charge);
+    trigger.bind::<ReelIn>().to(&bindings.reel_in);
+    trigger.bind::<ReelOut>().to(&bindings.reel_out);
+    trigger.bind::<Pause>().to(&bindings.pause);
+}
+
+// Action state of the diver's input context.
+#[derive(SystemParam)]
+pub struct PlayerActions<'w, 's> {
+    registry: Res<'w, InputContextRegistry>,
+    player: Query<'w, 's, Entity, With<Player>>,
+}
+
+impl PlayerActions<'_, '_> {
+    pub fn get<A: InputAction>(&self) -> Option<&ActionData> {
+        let player = self.player.get_single().ok()?;
+        self.registry.get_context::<Player>(player)?.get_action::<A>()
+    }
+
+    pub fn value<A: InputAction>(&self) -> ActionValue {
+        self.get::<A>().map_or(ActionValue::Bool(false), |action| action.value())
+    }
+
+    pub fn started<A: InputAction>(&self) -> bool {
+        self.get::<A>().is_some_and(|action| action.events().contains(ActionEvents::STARTED))
+    }
+
+    pub fn completed<A: InputAction>(&self) -> bool {
+        self.get::<A>().is_some_and(|action| action.events().contains(ActionEvents::COMPLETED))
+    }
+}
+
+pub fn rebind_input_system(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    gamepads: Query<&Gamepad>,
+    mut rebinding: ResMut<Rebinding>,
+    mut bindings: ResMut<InputBindings>,
+) {
+    let Some(slot) = rebinding.0 else {
+        // Digit keys pick the slot to rebind.
+        let digits = [KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4, KeyCode::Digit5, KeyCode::Digit6, KeyCode::Digit7, KeyCode::Digit8, KeyCode::Digit9];
+        rebinding.0 = digits.iter().position(|key| keys.just_pressed(*key)).map(|index| BindingSlot::ALL[index]);
+        return;
+    };
+
+    let pad_button = gamepads.iter().find_map(|gamepad| gamepad.get_just_pressed().next().copied());
+    let input: Input = if slot.is_analog() {
+        // Only the analog triggers keep a charge slot analog; keys and face buttons would make it on/off.
+        let Some(button) = pad_button.filter(|button| ANALOG_BUTTONS.contains(button)) else { return };
+        button.into()
+    } else if let Some(key) = keys.get_just_pressed().next() {
+        (*key).into()
+    } else if let Some(button) = mouse.get_just_pressed().next() {
+        (*button).into()
+    } else if let Some(button) = pad_button {
+        button.into()
+    } else {
+        return;
+    };
+
+    let inputs = bindings.slot_mut(slot);
+    inputs.retain(|existing| *existing != input);
+    match inputs.first_mut() {
+        Some(first) => *first = input,
+        None => inputs.push(input),
+    }
+    bindings.save();
+    rebinding.0 = None;
+    commands.trigger(RebuildBindings);
+}
+
+// Screen pixels the finger has to travel from where it landed for full speed.
+pub const JOYSTICK_RADIUS: f32 = 60.0;
+
+// Swim stick for touch screens, grabbed by any finger landing on the left half of the window.
+#[derive(Resource, Default)]
+pub struct VirtualJoystick {
+    pub touch: Option<u64>,
+    pub origin: Vec2,
+    pub direction: Vec2,
+}
+
+pub fn virtual_joystick_system(
+    touches: Res<Touches>,
+    windows: Query<&Window>,
+    drag_state: Res<DragState>,
+    mut joystick: ResMut<VirtualJoystick>,
+) {
+    let Ok(window) = windows.get_single() else { return };
+    if joystick.touch.is_none() {
+        let finger = touches.iter_just_pressed()
+            .find(|touch| drag_state.touch != Some(touch.id()) && touch.position().x < window.width() / 2.0);
+        if let Some(finger) = finger {
+            joystick.touch = Some(finger.id());
+            joystick.origin = finger.position();
+        }
+    }
+
+    let Some(id) = joystick.touch else { return };
+    match touches.get_pressed(id) {
+        // Window coordinates grow downwards.
+        Some(finger) => joystick.direction = ((finger.position() - joystick.origin) * Vec2::new(1.0, -1.0) / JOYSTICK_RADIUS).clamp_length_max(1.0),
+        None => *joystick = VirtualJoystick::default(),
+    }
+}
+
+pub fn cancel_rebinding(mut rebinding: ResMut<Rebinding>) {
+    rebinding.0 = None;
+}
@@ -0,0 +1,98 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+// Static level geometry, always on `Layer::World`.
+#[derive(Component)]
+pub struct Wall;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Layer {
+    World,
+    Diver,
+    Ball,
+    RopeSegment,
+    Fish,
+    // Triggers that only report overlaps.
+    Sensor,
+}
+
+impl Layer {
+    pub const ALL: [Layer; 6] = [Layer::World, Layer::Diver, Layer::Ball, Layer::RopeSegment, Layer::Fish, Layer::Sensor];
+
+    pub fn group(self) -> Group {
+        match self {
+            Layer::World => Group::GROUP_1,
+            Layer::Diver => Group::GROUP_2,
+            Layer::Ball => Group::GROUP_3,
+            Layer::RopeSegment => Group::GROUP_4,
+            Layer::Fish => Group::GROUP_5,
+            Layer::Sensor => Group::GROUP_6,
+        }
+    }
+
+    // The interaction matrix. Has to stay symmetric: a pair only touches when both sides list each other.
+    pub fn interacts_with(self) -> &'static [Layer] {
+        match self {
+            Layer::World => &[Layer::Diver, Layer::Ball, Layer::RopeSegment, Layer::Fish],
+            Layer::Diver => &[Layer::World, Layer::Ball, Layer::Fish, Layer::Sensor],
+            Layer::Ball => &[Layer::World, Layer::Diver, Layer::Fish, Layer::Sensor],
+            Layer::RopeSegment => &[Layer::World],
+            Layer::Fish => &[Layer::World, Layer::Diver, Layer::Ball, Layer::Fish],
+            Layer::Sensor => &[Layer::Diver, Layer::Ball],
+        }
+    }
+
+    pub fn filter(self) -> Group {
+        self.interacts_with().iter().fold(Group::NONE, |filter, layer| filter | layer.group())
+    }
+
+    pub fn groups(self) -> CollisionGroups {
+        CollisionGroups::new(self.group(), self.filter())
+    }
+
+    // Scene query hitting only colliders on this layer, e.g. walls for line of sight.
+    pub fn query(self) -> QueryFilter<'static> {
+        QueryFilter::new().groups(CollisionGroups::new(Group::ALL, self.group()))
+    }
+
+    fn from_group(group: Group) -> Option<Layer> {
+        Layer::ALL.into_iter().find(|layer| layer.group() == group)
+    }
+}
+
+fn describe(entity: Entity, name: Option<&Name>) -> String {
+    name.map_or(entity.to_string(), |name| format!("{name} ({entity})"))
+}
+
+// Debug builds only: catches colliders set up with raw groups that drifted from the matrix,
+// or with no groups at all, which collide with everything.
+pub fn check_collision_layers_system(
+    groups: Query<(Entity, &CollisionGroups, Option<&Name>), Changed<CollisionGroups>>,
+    ungrouped: Query<(Entity, Option<&Name>), (Added<Collider>, Without<CollisionGroups>)>,
+) {
+    for (entity, name) in &ungrouped {
+        warn!("{} has a collider but no collision layer", describe(entity, name));
+    }
+    for (entity, groups, name) in &groups {
+        let name = describe(entity, name);
+        match Layer::from_group(groups.memberships) {
+            Some(layer) if groups.filters == layer.filter() => {}
+            Some(layer) => warn!("{name} is on {layer:?} but collides with {:?} instead of {:?}", groups.filters, layer.filter()),
+            None => warn!("{name} has memberships {:?} that are not a single collision layer", groups.memberships),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interaction_matrix_is_symmetric() {
+        for a in Layer::ALL {
+            for b in Layer::ALL {
+                assert_eq!(a.interacts_with().contains(&b), b.interacts_with().contains(&a), "{a:?} and {b:?}");
+            }
+        }
+    }
+}
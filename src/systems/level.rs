@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy_rapier2d::prelude::*;
+use noisy_bevy::simplex_noise_2d;
+use crate::game_state::*;
+use crate::scene::*;
+use crate::layers::*;
+
+const CHUNK_HEIGHT: f32 = 1000.0;
+// Vertical spacing of the points along a wall's edge.
+const WALL_STEP: f32 = 50.0;
+const WALL_THICKNESS: f32 = 300.0;
+// How far the walls wander into the shaft.
+const WALL_AMPLITUDE: f32 = 120.0;
+const WALL_FREQUENCY: f32 = 1.0 / 900.0;
+// Nothing sticks out of the walls below this, so the run starts in open water.
+const CLEAR_START: f32 = 400.0;
+const ROCK_COLOR: Color = Color::srgb(0.16, 0.2, 0.26);
+
+// Seed of the current run, picked up from `LEVEL_SEED` when set so a layout can be replayed.
+pub fn level_seed() -> u32 {
+    let seed = std::env::var("LEVEL_SEED").ok()
+        .and_then(|seed| seed.parse().ok())
+        .unwrap_or_else(|| RandomState::new().build_hasher().finish() as u32);
+    info!("level seed {seed}");
+    seed
+}
+
+#[derive(Resource)]
+pub struct Level {
+    pub seed: u32,
+    // Height the generated shaft starts at, above the hand-authored room.
+    pub start: f32,
+    // Where in the noise field this seed's walls are sampled, see `noise_offset`.
+    noise_offset: Vec2,
+    chunks: HashMap<i32, Entity>,
+    material: Option<Handle<ColorMaterial>>,
+}
+
+// Every bit of the seed moves the sample window. The irrational scale keeps seeds from landing on
+// the same spot of the noise's 289-cell period.
+fn noise_offset(seed: u32) -> Vec2 {
+    Vec2::new((seed & 0xFFFF) as f32, (seed >> 16) as f32) * 0.618_034
+}
+
+impl Level {
+    pub fn new(seed: u32, start: f32) -> Self {
+        Self { seed, start, noise_offset: noise_offset(seed), chunks: HashMap::new(), material: None }
+    }
+
+    // Inner edge of the left (-1) or right (1) wall at a height. Depends on the seed alone,
+    // so neighbouring chunks meet without seams.
+    fn wall_x(&self, bounds: &LevelBounds, side: f32, y: f32) -> f32 {
+        let noise = simplex_noise_2d(Vec2::new(y * WALL_FREQUENCY, side * 7.0) + self.noise_offset);
+        let inset = (noise * 0.5 + 0.5) * WALL_AMPLITUDE * ((y - self.start) / CLEAR_START).clamp(0.0, 1.0);
+        let edge = if side < 0.0 { bounds.min.x } else { bounds.max.x };
+        edge - side * inset
+    }
+}
+
+// xorshift32 seeded per chunk, so a chunk comes back the same after streaming out.
+struct ChunkRng(u32);
+
+impl ChunkRng {
+    fn new(seed: u32, chunk: i32) -> Self {
+        Self((seed ^ (chunk as u32).wrapping_mul(0x9E37_79B9)).max(1))
+    }
+
+    fn next_f32(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+
+    fn range(&mut self, min: f32, max: f32) -> f32 {
+        min.lerp(max, self.next_f32())
+    }
+}
+
+// Convex polygons making up one chunk, in world space.
+fn generate_chunk(level: &Level, bounds: &LevelBounds, chunk: i32) -> Vec<Vec<Vec2>> {
+    let mut rng = ChunkRng::new(level.seed, chunk);
+    let bottom = level.start + chunk as f32 * CHUNK_HEIGHT;
+    let steps = (CHUNK_HEIGHT / WALL_STEP) as usize;
+    let mut polygons = Vec::new();
+
+    for side in [-1.0, 1.0] {
+        let outer = if side < 0.0 { bounds.min.x } else { bounds.max.x } + side * WALL_THICKNESS;
+        for step in 0..steps {
+            let y0 = bottom + step as f32 * WALL_STEP;
+            let y1 = y0 + WALL_STEP;
+            polygons.push(vec![
+                Vec2::new(outer, y0),
+                Vec2::new(level.wall_x(bounds, side, y0), y0),
+                Vec2::new(level.wall_x(bounds, side, y1), y1),
+                Vec2::new(outer, y1),
+            ]);
+        }
+
+        // Ledges jutting out of the wall, sloped so things slide off them.
+        for _ in 0..2 {
+            let y = bottom + rng.range(0.0, CHUNK_HEIGHT);
+            if y < level.start + CLEAR_START || rng.next_f32() < 0.4 { continue }
+            let root = level.wall_x(bounds, side, y) + side * 20.0;
+            let reach = rng.range(80.0, 180.0);
+            let thickness = rng.range(30.0, 60.0);
+            let drop = rng.range(-40.0, 40.0);
+            polygons.push(vec![
+                Vec2::new(root, y - thickness),
+                Vec2::new(root - side * reach, y + drop),
+                Vec2::new(root, y + thickness),
+            ]);
+        }
+    }
+
+    // Loose boulders, kept off the centre line so the shaft always stays passable.
+    let count = rng.range(0.0, 3.0) as usize;
+    for _ in 0..count {
+        let y = bottom + rng.range(0.0, CHUNK_HEIGHT);
+        if y < level.start + CLEAR_START { continue }
+        let side = if rng.next_f32() < 0.5 { -1.0 } else { 1.0 };
+        let center = Vec2::new(side * rng.range(100.0, 200.0), y);
+        let radius = rng.range(35.0, 70.0);
+        let corners = 5 + (rng.next_f32() * 3.0) as usize;
+        let start = rng.range(0.0, std::f32::consts::TAU);
+        polygons.push((0..corners).map(|i| {
+            let angle = start + i as f32 / corners as f32 * std::f32::consts::TAU;
+            center + Vec2::from_angle(angle) * radius * rng.range(0.75, 1.0)
+        }).collect());
+    }
+
+    polygons
+}
+
+fn chunk_mesh(polygons: &[Vec<Vec2>]) -> Mesh {
+    let mut positions = Vec::new();
+    let mut indices = Vec::new();
+    for polygon in polygons {
+        let first = positions.len() as u32;
+        positions.extend(polygon.iter().map(|point| [point.x, point.y, 0.0]));
+        // Every polygon is convex, so a fan covers it.
+        for i in 1..polygon.len() as u32 - 1 { indices.extend([first, first + i, first + i + 1]); }
+    }
+    let count = positions.len();
+    Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default())
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0, 0.0, 1.0]; count])
+        .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0, 0.0]; count])
+        .with_inserted_indices(Indices::U32(indices))
+}
+
+fn spawn_chunk(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    material: Handle<ColorMaterial>,
+    polygons: &[Vec<Vec2>],
+) -> Entity {
+    // Degenerate polygons have no hull; they are left out of the mesh too so nothing visible is intangible.
+    let (solid, shapes): (Vec<Vec<Vec2>>, Vec<_>) = polygons.iter()
+        .filter_map(|polygon| Some((polygon.clone(), (Vec2::ZERO, 0.0, Collider::convex_hull(polygon)?))))
+        .unzip();
+
+    commands
+        .spawn(Collider::compound(shapes))
+        .insert(Wall)
+        .insert(Layer::World.groups())
+        .insert(Mesh2d(meshes.add(chunk_mesh(&solid))))
+        .insert(MeshMaterial2d(material))
+        .insert(Transform::from_xyz(0.0, 0.0, -0.2))
+        .insert(StateScoped(InGame))
+        .id()
+}
+
+// Keeps the chunks within a screen of the camera built and drops the rest.
+pub fn stream_level_system(
+    mut commands: Commands,
+    mut level: ResMut<Level>,
+    bounds: Res<LevelBounds>,
+    camera: Query<(&GlobalTransform, &OrthographicProjection), With<Camera2d>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let Ok((camera, projection)) = camera.get_single() else { return };
+    let y = camera.translation().y;
+    let reach = projection.area.height();
+    let last = ((bounds.max.y - level.start) / CHUNK_HEIGHT).ceil() as i32 - 1;
+    let first_visible = (((y - reach - level.start) / CHUNK_HEIGHT).floor() as i32).max(0);
+    let last_visible = (((y + reach - level.start) / CHUNK_HEIGHT).floor() as i32).min(last);
+
+    level.chunks.retain(|chunk, entity| {
+        if (first_visible..=last_visible).contains(chunk) { return true }
+        commands.entity(*entity).despawn();
+        false
+    });
+
+    let material = level.material.get_or_insert_with(|| materials.add(ROCK_COLOR)).clone();
+    for chunk in first_visible..=last_visible {
+        if level.chunks.contains_key(&chunk) { continue }
+        let polygons = generate_chunk(&level, &bounds, chunk);
+        let entity = spawn_chunk(&mut commands, &mut meshes, material.clone(), &polygons);
+        level.chunks.insert(chunk, entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDS: LevelBounds = LevelBounds { min: Vec2::new(-375.0, 0.0), max: Vec2::new(375.0, 14000.0) };
+
+    // Recorded from seed 1234, any change here moves every shared seed's layout.
+    #[test]
+    fn chunks_are_reproducible_from_the_seed() {
+        let chunk = generate_chunk(&Level::new(1234, 0.0), &BOUNDS, 1);
+        assert_eq!(chunk.len(), 45);
+
+        let walls = [chunk[0][1], chunk[19][2], chunk[22][1]];
+        let walls_expected = [Vec2::new(-291.64206, 1000.0), Vec2::new(-357.48285, 2000.0), Vec2::new(291.6882, 1000.0)];
+        let props: Vec<Vec2> = chunk.iter().filter(|polygon| polygon.len() != 4).map(|polygon| polygon[0]).collect();
+        let props_expected = [
+            Vec2::new(-363.4073, 1207.998),
+            Vec2::new(-367.54962, 1444.3872),
+            Vec2::new(306.0379, 1288.3062),
+            Vec2::new(169.09174, 1218.6978),
+            Vec2::new(-158.98166, 1098.994),
+        ];
+        assert_eq!(props.len(), props_expected.len());
+        for (point, expected) in walls.iter().chain(&props).zip(walls_expected.iter().chain(&props_expected)) {
+            assert!(point.distance(*expected) < 0.01, "{point} != {expected}");
+        }
+    }
+
+    #[test]
+    fn different_seeds_give_different_chunks() {
+        for chunk in 0..4 {
+            let first = generate_chunk(&Level::new(1234, 0.0), &BOUNDS, chunk);
+            let second = generate_chunk(&Level::new(4321, 0.0), &BOUNDS, chunk);
+            assert_ne!(first, second);
+        }
+    }
+
+    #[test]
+    fn walls_use_the_whole_seed() {
+        let wall = |seed| Level::new(seed, 0.0).wall_x(&BOUNDS, -1.0, 3000.0);
+        assert_ne!(wall(7), wall(1007));
+        assert_ne!(wall(7), wall(7 + (1 << 16)));
+    }
+}
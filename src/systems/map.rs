@@ -0,0 +1,330 @@
+use bevy::prelude::*;
+use bevy::asset::{AssetLoader, LoadContext, io::Reader};
+use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
+use thiserror::Error;
+use crate::player::*;
+use crate::enemy::*;
+use crate::game_state::*;
+use crate::assets::*;
+use crate::collision::*;
+use crate::layers::*;
+
+// Only the parts of a Tiled JSON map (`.tmj`) the game reads.
+#[derive(Deserialize)]
+struct TiledMap {
+    width: u32,
+    height: u32,
+    tilewidth: u32,
+    tileheight: u32,
+    layers: Vec<TiledLayer>,
+    #[serde(default)]
+    properties: Vec<TiledProperty>,
+}
+
+#[derive(Deserialize)]
+struct TiledLayer {
+    name: String,
+    #[serde(default)]
+    objects: Vec<TiledObject>,
+    // Children of a group layer.
+    #[serde(default)]
+    layers: Vec<TiledLayer>,
+}
+
+#[derive(Deserialize)]
+struct TiledObject {
+    #[serde(default)]
+    name: String,
+    // Tiled 1.9 wrote this as `class`, later versions went back to `type`.
+    #[serde(default, rename = "type", alias = "class")]
+    class: String,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    // Degrees clockwise around the object's origin.
+    #[serde(default)]
+    rotation: f32,
+    #[serde(default)]
+    ellipse: bool,
+    #[serde(default)]
+    polygon: Option<Vec<TiledPoint>>,
+    #[serde(default)]
+    polyline: Option<Vec<TiledPoint>>,
+    #[serde(default)]
+    properties: Vec<TiledProperty>,
+}
+
+#[derive(Deserialize)]
+struct TiledPoint {
+    x: f32,
+    y: f32,
+}
+
+#[derive(Deserialize)]
+struct TiledProperty {
+    name: String,
+    value: serde_json::Value,
+}
+
+fn property<'a>(properties: &'a [TiledProperty], name: &str) -> Option<&'a serde_json::Value> {
+    properties.iter().find(|property| property.name == name).map(|property| &property.value)
+}
+
+// World-space outline of a map object.
+#[derive(Clone, Debug)]
+pub enum MapShape {
+    Polygon(Vec<Vec2>),
+    Polyline(Vec<Vec2>),
+    Circle(Vec2, f32),
+}
+
+impl MapShape {
+    pub fn collider(&self) -> Collider {
+        match self {
+            MapShape::Polygon(points) => {
+                // Concave outlines are split into convex parts.
+                let ring = (0..points.len() as u32).map(|i| [i, (i + 1) % points.len() as u32]).collect::<Vec<_>>();
+                Collider::convex_decomposition(points, &ring)
+            }
+            MapShape::Polyline(points) => Collider::polyline(points.clone(), None),
+            MapShape::Circle(center, radius) => Collider::compound(vec![(*center, 0.0, Collider::ball(*radius))]),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SpawnKind {
+    Player,
+    Ball,
+    Fish(FishKind),
+}
+
+#[derive(Clone, Debug)]
+pub struct SpawnMarker {
+    pub kind: SpawnKind,
+    pub position: Vec2,
+}
+
+#[derive(Clone, Debug)]
+pub struct Decoration {
+    pub image: Handle<Image>,
+    pub center: Vec2,
+    pub size: Vec2,
+    pub rotation: f32,
+    pub z: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct TriggerArea {
+    pub name: String,
+    pub shape: MapShape,
+}
+
+// A hand-authored room. Tiled's y-down pixels are flipped so the bottom of the map sits on y = 0,
+// centred on x = 0.
+#[derive(Asset, TypePath, Debug)]
+pub struct LevelMap {
+    pub size: Vec2,
+    // How far above the map the shaft goes on, from the map's `depth` property.
+    pub depth: f32,
+    pub walls: Vec<MapShape>,
+    pub spawns: Vec<SpawnMarker>,
+    pub decorations: Vec<Decoration>,
+    pub triggers: Vec<TriggerArea>,
+}
+
+impl LevelMap {
+    pub fn spawn_point(&self, kind: SpawnKind) -> Option<Vec2> {
+        self.spawns.iter().find(|spawn| spawn.kind == kind).map(|spawn| spawn.position)
+    }
+}
+
+#[derive(Default)]
+pub struct LevelMapLoader;
+
+#[derive(Debug, Error)]
+pub enum LevelMapLoaderError {
+    #[error("could not read map: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse map: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("spawn `{0}` is not Player, Ball or Fish")]
+    UnknownSpawn(String),
+    #[error("fish spawn `{0}` has an unknown `kind`")]
+    UnknownFishKind(String),
+    #[error("the map has no {0} spawn")]
+    MissingSpawn(&'static str),
+    #[error("decoration `{0}` has no `image` property")]
+    MissingImage(String),
+    #[error("`{0}` on layer `{1}` is a point and has no shape")]
+    NoShape(String, String),
+}
+
+impl AssetLoader for LevelMapLoader {
+    type Asset = LevelMap;
+    type Settings = ();
+    type Error = LevelMapLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<LevelMap, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let file: TiledMap = serde_json::from_slice(&bytes)?;
+
+        let size = Vec2::new((file.width * file.tilewidth) as f32, (file.height * file.tileheight) as f32);
+        let depth = property(&file.properties, "depth").and_then(|value| value.as_f64()).map_or(size.y, |depth| depth as f32);
+        let mut map = LevelMap { size, depth, walls: Vec::new(), spawns: Vec::new(), decorations: Vec::new(), triggers: Vec::new() };
+
+        let mut layers: Vec<&TiledLayer> = file.layers.iter().collect();
+        while let Some(layer) = layers.pop() {
+            layers.extend(&layer.layers);
+            for object in &layer.objects {
+                let shape = object_shape(object, size);
+                let shape_or_error = || shape.clone().ok_or_else(|| LevelMapLoaderError::NoShape(object.name.clone(), layer.name.clone()));
+                match layer.name.to_lowercase().as_str() {
+                    "walls" => map.walls.push(shape_or_error()?),
+                    "triggers" => map.triggers.push(TriggerArea { name: object.name.clone(), shape: shape_or_error()? }),
+                    "spawns" => {
+                        let kind = match object.class.as_str() {
+                            "Player" => SpawnKind::Player,
+                            "Ball" => SpawnKind::Ball,
+                            "Fish" => {
+                                let kind = property(&object.properties, "kind").cloned().unwrap_or("Player".into());
+                                SpawnKind::Fish(serde_json::from_value(kind).map_err(|_| LevelMapLoaderError::UnknownFishKind(object.name.clone()))?)
+                            }
+                            other => return Err(LevelMapLoaderError::UnknownSpawn(other.to_string())),
+                        };
+                        map.spawns.push(SpawnMarker { kind, position: to_world(Vec2::new(object.x, object.y), size) });
+                    }
+                    "decorations" => {
+                        let Some(image) = property(&object.properties, "image").and_then(|value| value.as_str()) else {
+                            return Err(LevelMapLoaderError::MissingImage(object.name.clone()));
+                        };
+                        let z = property(&object.properties, "z").and_then(|value| value.as_f64()).unwrap_or(0.0) as f32;
+                        let center = rotate(Vec2::new(object.width, object.height) / 2.0, object.rotation) + Vec2::new(object.x, object.y);
+                        map.decorations.push(Decoration {
+                            image: load_context.load(image.to_string()),
+                            center: to_world(center, size),
+                            size: Vec2::new(object.width, object.height),
+                            rotation: -object.rotation.to_radians(),
+                            z,
+                        });
+                    }
+                    other => warn!("ignoring object `{}` on unknown map layer `{other}`", object.name),
+                }
+            }
+        }
+
+        if map.spawn_point(SpawnKind::Player).is_none() { return Err(LevelMapLoaderError::MissingSpawn("Player")) }
+        if map.spawn_point(SpawnKind::Ball).is_none() { return Err(LevelMapLoaderError::MissingSpawn("Ball")) }
+        Ok(map)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tmj"]
+    }
+}
+
+fn to_world(point: Vec2, size: Vec2) -> Vec2 {
+    Vec2::new(point.x - size.x / 2.0, size.y - point.y)
+}
+
+// Clockwise in Tiled's y-down space.
+fn rotate(point: Vec2, degrees: f32) -> Vec2 {
+    Vec2::from_angle(degrees.to_radians()).rotate(point)
+}
+
+fn object_shape(object: &TiledObject, size: Vec2) -> Option<MapShape> {
+    let origin = Vec2::new(object.x, object.y);
+    let place = |points: &[Vec2]| points.iter().map(|point| to_world(origin + rotate(*point, object.rotation), size)).collect::<Vec<_>>();
+    let outline = |points: &[TiledPoint]| place(&points.iter().map(|point| Vec2::new(point.x, point.y)).collect::<Vec<_>>());
+
+    if let Some(points) = &object.polygon { return Some(MapShape::Polygon(outline(points))) }
+    if let Some(points) = &object.polyline { return Some(MapShape::Polyline(outline(points))) }
+    if object.width <= 0.0 || object.height <= 0.0 { return None }
+    if object.ellipse {
+        let center = place(&[Vec2::new(object.width, object.height) / 2.0])[0];
+        return Some(MapShape::Circle(center, object.width.min(object.height) / 2.0));
+    }
+    Some(MapShape::Polygon(place(&[Vec2::ZERO, Vec2::new(object.width, 0.0), Vec2::new(object.width, object.height), Vec2::new(0.0, object.height)])))
+}
+
+#[derive(Component)]
+pub struct MapTrigger(pub String);
+
+#[derive(Event)]
+#[allow(dead_code)]
+pub struct MapTriggerEvent {
+    pub trigger: String,
+    pub entity: Entity,
+}
+
+pub fn spawn_level_map(commands: &mut Commands, map: &LevelMap) {
+    for wall in &map.walls {
+        commands
+            .spawn(wall.collider())
+            .insert(Wall)
+            .insert(Transform::default())
+            .insert(Layer::World.groups())
+            .insert(StateScoped(InGame));
+    }
+
+    for trigger in &map.triggers {
+        commands
+            .spawn(trigger.shape.collider())
+            .insert(Sensor)
+            .insert(MapTrigger(trigger.name.clone()))
+            .insert(ActiveEvents::COLLISION_EVENTS)
+            .insert(Layer::Sensor.groups())
+            .insert(Transform::default())
+            .insert(StateScoped(InGame));
+    }
+
+    for decoration in &map.decorations {
+        commands
+            .spawn(Sprite {
+                image: decoration.image.clone(),
+                custom_size: Some(decoration.size),
+                ..default()
+            })
+            .insert(Transform::from_translation(decoration.center.extend(decoration.z)).with_rotation(Quat::from_rotation_z(decoration.rotation)))
+            .insert(StateScoped(InGame));
+    }
+}
+
+pub fn spawn_map_fish(
+    mut commands: Commands,
+    maps: Res<Assets<LevelMap>>,
+    levels: Res<LevelAssets>,
+    textures: Res<TextureAssets>,
+    animations: Res<AnimationAssets>,
+) {
+    let Some(map) = maps.get(&levels.start) else { return };
+    for spawn in &map.spawns {
+        let SpawnKind::Fish(kind) = spawn.kind else { continue };
+        spawn_fish(&mut commands, &textures, &animations, kind, spawn.position);
+    }
+}
+
+pub fn map_trigger_system(
+    mut divers: EventReader<Collided<MapTrigger, Player>>,
+    mut balls: EventReader<Collided<MapTrigger, Ball>>,
+    mut triggered: EventWriter<MapTriggerEvent>,
+    triggers: Query<&MapTrigger>,
+) {
+    let entered = divers.read().filter(|contact| contact.started()).map(|contact| (contact.a, contact.b))
+        .chain(balls.read().filter(|contact| contact.started()).map(|contact| (contact.a, contact.b)));
+    for (trigger, entity) in entered {
+        let Ok(trigger) = triggers.get(trigger) else { continue };
+        info!("{entity} entered trigger `{}`", trigger.0);
+        triggered.send(MapTriggerEvent { trigger: trigger.0.clone(), entity });
+    }
+}
@@ -0,0 +1,123 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use crate::steering::*;
+use crate::layers::*;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Awareness {
+    #[default]
+    Patrol,
+    // Prey spotted, turning towards it before committing.
+    Alert,
+    Chase,
+    // Prey out of sight, searching around where it was last seen.
+    LostTrack,
+}
+
+#[derive(Component)]
+pub struct Perception {
+    pub radius: f32,
+    // Full width of the view cone, in radians.
+    pub fov: f32,
+    pub alert_time: f32,
+    pub memory: f32,
+    pub search_time: f32,
+    pub prey: Option<Entity>,
+    pub state: Awareness,
+    pub timer: f32,
+    pub last_seen: Option<Vec2>,
+}
+
+impl Perception {
+    pub fn new(radius: f32, fov: f32) -> Self {
+        Self {
+            radius,
+            fov,
+            alert_time: 0.4,
+            memory: 1.5,
+            search_time: 3.0,
+            prey: None,
+            state: Awareness::Patrol,
+            timer: 0.0,
+            last_seen: None,
+        }
+    }
+}
+
+// Steering behaviours swapped in when the awareness state changes.
+#[derive(Component)]
+pub struct AwarenessBehaviours {
+    pub patrol: Vec<(Behaviour, f32)>,
+    pub alert: Vec<(Behaviour, f32)>,
+    pub chase: Vec<(Behaviour, f32)>,
+    pub lost_track: Vec<(Behaviour, f32)>,
+}
+
+impl AwarenessBehaviours {
+    pub fn get(&self, state: Awareness) -> &[(Behaviour, f32)] {
+        match state {
+            Awareness::Patrol => &self.patrol,
+            Awareness::Alert => &self.alert,
+            Awareness::Chase => &self.chase,
+            Awareness::LostTrack => &self.lost_track,
+        }
+    }
+}
+
+fn next_state(perception: &Perception, visible: bool) -> Awareness {
+    match (perception.state, visible) {
+        (Awareness::Patrol, true) => Awareness::Alert,
+        (Awareness::Alert, true) if perception.timer >= perception.alert_time => Awareness::Chase,
+        (Awareness::Alert, false) => Awareness::Patrol,
+        (Awareness::Chase, false) if perception.timer >= perception.memory => Awareness::LostTrack,
+        (Awareness::LostTrack, true) => Awareness::Chase,
+        (Awareness::LostTrack, false) if perception.timer >= perception.search_time => Awareness::Patrol,
+        (state, _) => state,
+    }
+}
+
+pub fn perception_system(
+    time: Res<Time>,
+    rapier: ReadRapierContext,
+    transforms: Query<&GlobalTransform>,
+    mut query: Query<(Entity, &GlobalTransform, &mut Perception, &mut Steering, Option<&AwarenessBehaviours>), Without<Stunned>>,
+) {
+    let context = rapier.single();
+    // Only the walls and the ground block sight.
+    let walls = Layer::World.query();
+
+    for (entity, transform, mut perception, mut steering, behaviours) in &mut query {
+        let position = transform.translation().truncate();
+        let forward = transform.up().truncate();
+        let prey_position = perception.prey
+            .and_then(|prey| transforms.get(prey).ok())
+            .map(|t| t.translation().truncate());
+
+        let visible = prey_position.is_some_and(|prey| {
+            let offset = prey - position;
+            let distance = offset.length();
+            if distance > perception.radius { return false }
+            if distance <= f32::EPSILON { return true }
+            if forward.angle_to(offset).abs() > perception.fov / 2.0 { return false }
+            let blocked = context.cast_ray(position, offset / distance, distance, true, walls.exclude_collider(entity));
+            blocked.is_none()
+        });
+
+        if visible { perception.last_seen = prey_position }
+        if visible && perception.state == Awareness::Chase { perception.timer = 0.0 }
+        perception.timer += time.delta_secs();
+
+        let state = next_state(&perception, visible);
+        if state != perception.state {
+            perception.state = state;
+            perception.timer = 0.0;
+            if let Some(behaviours) = behaviours { steering.behaviours = behaviours.get(state).to_vec() }
+        }
+
+        match state {
+            Awareness::Patrol => (steering.target, steering.point) = (None, None),
+            Awareness::Alert | Awareness::Chase => (steering.target, steering.point) = (perception.prey, None),
+            Awareness::LostTrack => (steering.target, steering.point) = (None, perception.last_seen),
+        }
+    }
+}
@@ -3,7 +3,15 @@ use bevy_rapier2d::prelude::*;
 use crate::hover::*;
 use crate::vec2;
 use crate::animation::*;
-use std::collections::HashMap;
+use crate::state_machine::*;
+use crate::health::*;
+use crate::game_state::*;
+use crate::assets::*;
+use crate::input::*;
+use crate::tether::*;
+use crate::map::*;
+use crate::layers::*;
+use std::f32::consts::FRAC_PI_2;
 
 #[derive(Component)]
 pub struct Player;
@@ -14,56 +22,63 @@ pub struct Ball;
 #[derive(Component)]
 pub struct Ring;
 
-#[derive(Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Eq, Hash, PartialEq)]
 pub enum PlayerAnimation {
     Idle,
     Swimming,
     Trackted,
 }
 
-const HEIGHT: u32 = 848;
-const WIDTH: u32 = 496;
-const AVATAR_RATIO: f32 = WIDTH as f32 / HEIGHT as f32;
-const AVATAR_SIZE: f32 = 350.0;
-
-const NUM_OF_RINGS: usize = 0;
-const STEP_ROPE_DISTANCE: f32 = 100.0;
-const EDGE_DISTANCE: f32 = 11.0;
-const RING_RATIO: f32 = 1.0;
-const RING_SIZE: f32 = 20.0;
-
-pub fn setup_player(mut commands: Commands, asset_server: Res<AssetServer>, mut texture_atlas_layouts: ResMut<Assets<TextureAtlasLayout>>) {
-    let texture = asset_server.load("textures/player.png");
-    let layout = TextureAtlasLayout::from_grid(UVec2 { x: WIDTH, y: HEIGHT }, 5, 2, None, None);
-    let texture_atlas_layout = texture_atlas_layouts.add(layout);
+impl AnimationKey for PlayerAnimation {
+    fn clip_name(&self) -> &'static str {
+        match self {
+            PlayerAnimation::Idle => "Idle",
+            PlayerAnimation::Swimming => "Swimming",
+            PlayerAnimation::Trackted => "Trackted",
+        }
+    }
+}
 
-    let mut player_sprite = Sprite::from_atlas_image(
-        texture,
+const AVATAR_SIZE: f32 = 350.0;
+pub const BALL_RADIUS: f32 = 25.0;
+// Longest pull that still adds power, in world units.
+pub const MAX_PULL: f32 = 250.0;
+const FLING_IMPULSE: f32 = 1_500_000.0;
+
+pub fn setup_player(
+    mut commands: Commands,
+    textures: Res<TextureAssets>,
+    animations: Res<AnimationAssets>,
+    tether: Res<TetherConfig>,
+    maps: Res<Assets<LevelMap>>,
+    levels: Res<LevelAssets>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let player_sprite = Sprite::from_atlas_image(
+        textures.player.clone(),
         TextureAtlas {
-            layout: texture_atlas_layout,
+            layout: animations.player_layout.clone(),
             index: 1,
         },
     );
-    player_sprite.custom_size = Some(vec2!(AVATAR_SIZE * AVATAR_RATIO, AVATAR_SIZE));
 
-    let mut clips = HashMap::new();
-    clips.insert(PlayerAnimation::Idle, AnimationSlice { first: 0, last: 1 });
-    clips.insert(PlayerAnimation::Trackted, AnimationSlice { first: 2, last: 2 });
-    clips.insert(PlayerAnimation::Swimming, AnimationSlice { first: 3, last: 5 });
+    let Some(map) = maps.get(&levels.start) else { return };
+    // The loader refuses maps without both spawns.
+    let (Some(player_position), Some(ball_position)) = (map.spawn_point(SpawnKind::Player), map.spawn_point(SpawnKind::Ball)) else { return };
 
-    let mut previous_entity =  commands
+    let player = commands
         .spawn(RigidBody::Dynamic)
         .insert(Player)
+        .insert(StateScoped(InGame))
+        .insert(Health::new(100.0, 1.0))
         .insert(Collider::capsule(vec2!(0.0, -10.0), vec2!(0.0, 45.0), 30.0))
         .insert(ActiveEvents::COLLISION_EVENTS)
         .insert(GravityScale(0.0))
         .insert(AdditionalMassProperties::Mass(1.0))
         .insert(ExternalForce::default())
         .insert(ExternalImpulse::default())
-        .insert(CollisionGroups::new(
-            Group::GROUP_2,
-            Group::GROUP_1 | Group::GROUP_2 | Group::GROUP_4,
-        ))
+        .insert(Layer::Diver.groups())
         .insert(Damping {
             linear_damping: 3.0,
             angular_damping: 1.0,
@@ -73,69 +88,38 @@ pub fn setup_player(mut commands: Commands, asset_server: Res<AssetServer>, mut
             angvel: 0.0,
         })
         .insert(player_sprite)
-        .insert(Animator {
-            current: PlayerAnimation::Idle,
-            clips: clips,
-            timer: Timer::from_seconds(0.3, TimerMode::Repeating),
-        })
-        .insert(Transform::from_xyz(100.0, 50.0, 0.0))
+        .insert(Animator::from_sheet(
+            animations.player.clone(),
+            PlayerAnimation::Idle,
+        ).with_height(AVATAR_SIZE))
+        .insert(AnimationStateMachine::new(PlayerAnimation::Idle)
+            .transition(Transition::to(PlayerAnimation::Swimming).when(AnimationCondition::Moving).priority(2))
+            .transition(Transition::to(PlayerAnimation::Trackted)
+                .when(AnimationCondition::NotMoving)
+                .when(AnimationCondition::SpeedAbove(20.0))
+                .priority(1))
+            .transition(Transition::to(PlayerAnimation::Idle)
+                .when(AnimationCondition::NotMoving)
+                .when(AnimationCondition::SpeedBelow(20.0)))
+            .min_dwell(PlayerAnimation::Trackted, 0.2))
+        .insert(Transform::from_translation(player_position.extend(0.0)))
         .id();
 
-    for i in 0..NUM_OF_RINGS {
-        let ring_texture = asset_server.load("textures/ring.png");
-        
-        let rope = RopeJointBuilder::new(STEP_ROPE_DISTANCE)
-            .local_anchor1(vec2!(if i == 0 { 0.0 } else { EDGE_DISTANCE }, 0.0))
-            .local_anchor2(vec2!(-EDGE_DISTANCE, 0.0));
-
-        previous_entity = commands
-            .spawn(RigidBody::Dynamic)
-            .insert(Ring)
-            .insert(Sprite {
-                image: ring_texture,
-                custom_size: Some(vec2!(RING_SIZE * RING_RATIO, RING_SIZE * RING_RATIO)),
-                ..default()
-            })
-            .insert(CollisionGroups::new(
-                Group::GROUP_3,
-                Group::GROUP_1 | Group::GROUP_4,
-            ))
-            .insert(GravityScale(1.0))
-            .insert(AdditionalMassProperties::Mass(1000.0))
-            .insert(Collider::capsule(vec2!(0.0, 0.0), vec2!(0.0, 0.0), RING_SIZE / 3.0))
-            .insert(Transform::from_xyz(
-                // (NUM_OF_RINGS - 1 - i) as f32 * (STEP_ROPE_DISTANCE + EDGE_DISTANCE * 2.0) * 0.5,
-                0.0,
-                50.0,
-                0.0,
-            ))
-            // .insert(ImpulseJoint::new(previous_entity, rope))
-            .insert_if(ImpulseJoint::new(previous_entity, rope), || { i > 0 })
-            .id();
-    }
-
-    let rope = RopeJointBuilder::new(STEP_ROPE_DISTANCE)
-        // .local_anchor1(vec2!(EDGE_DISTANCE, 0.0))
-        .local_anchor1(vec2!(0.0, 0.0))
-        .local_anchor2(vec2!(0.0, 25.0));
-
-    commands
+    let ball = commands
         .spawn(RigidBody::Dynamic)
         .insert(Ball)
+        .insert(StateScoped(InGame))
         .insert(Sprite {
-            image: asset_server.load("textures/ball.png"),
-            custom_size: Some(vec2!(50.0, 50.0)),
+            image: textures.ball.clone(),
+            custom_size: Some(vec2!(BALL_RADIUS * 2.0, BALL_RADIUS * 2.0)),
             ..default()
         })
-        .insert(CollisionGroups::new(
-            Group::GROUP_2,
-            Group::GROUP_1 | Group::GROUP_2,
-        ))
-        .insert(Collider::ball(25.0))
+        .insert(Layer::Ball.groups())
+        .insert(Collider::ball(BALL_RADIUS))
         .insert(ActiveEvents::COLLISION_EVENTS)
         .insert(GravityScale(10.0))
         .insert(AdditionalMassProperties::Mass(1_000_000.0))
-        .insert(Transform::from_xyz(0.0, 25.0, 0.0))
+        .insert(Transform::from_translation(ball_position.extend(0.0)))
         .insert(ExternalForce::default())
         .insert(ExternalImpulse::default())
         .insert(Hoverable)
@@ -144,46 +128,32 @@ pub fn setup_player(mut commands: Commands, asset_server: Res<AssetServer>, mut
             linear_damping: 0.5,
             angular_damping: 1.0,
         })
-        .insert(ImpulseJoint::new(previous_entity, rope))
         .insert(Velocity {
             linvel: vec2!(0.0, 0.0),
             angvel: 0.0,
-        });
+        })
+        .id();
+
+    spawn_tether(&mut commands, &tether, &mut meshes, &mut materials, (player, player_position), (ball, ball_position));
 }
 
 pub fn player_movement(
-    keys: Res<ButtonInput<KeyCode>>,
-    mut query: Query<(&mut ExternalForce, &mut ExternalImpulse, &mut Transform, &mut Animator<PlayerAnimation>, &Velocity), With<Player>>,
+    actions: PlayerActions,
+    joystick: Res<VirtualJoystick>,
+    mut query: Query<(&mut ExternalImpulse, &mut Transform, &mut AnimationParams), (With<Player>, Without<Dead>)>,
     time: Res<Time>,
 ) {
-    for (mut _force, mut impulse, mut transform, mut manager, velocity) in &mut query {
-        let mut direction = Vec2::ZERO;
-
-        if keys.pressed(KeyCode::KeyA) {
-            direction.x -= 1.0;
-        }
-        if keys.pressed(KeyCode::KeyD) {
-            direction.x += 1.0;
-        }
-        if keys.pressed(KeyCode::KeyW) {
-            direction.y += 1.0;
-        }
-        if keys.pressed(KeyCode::KeyS) {
-            direction.y -= 1.0;
-        }
+    for (mut impulse, mut transform, mut params) in &mut query {
+        let direction = (actions.value::<Swim>().as_axis2d() + joystick.direction).clamp_length_max(1.0);
 
         impulse.impulse = direction * 20_000.0;
+        params.intent = direction;
 
         if direction.length() > 0.1 {
-            manager.current = PlayerAnimation::Swimming;
-            let target_angle = direction.y.atan2(direction.x) - 3.1415926 / 2.0;
+            let target_angle = direction.y.atan2(direction.x) - FRAC_PI_2;
             let target_rotation = Quat::from_rotation_z(target_angle);
             let rotation_speed = 5.0;
             transform.rotation = transform.rotation.slerp(target_rotation, rotation_speed * time.delta_secs());
-        } else if velocity.linvel.length() > 20.0 {
-            manager.current = PlayerAnimation::Trackted;
-        } else {
-            manager.current = PlayerAnimation::Idle;
         }
     }
 }
@@ -193,9 +163,13 @@ pub fn apply_drag_impulse_system(
     mut events: EventReader<DragEndedEvent>,
 ) {
     for event in events.read() {
-        let Ok((mut impulse, velocity)) = impulses.get_mut(event.entity) else { continue };
+        let Ok((mut impulse, _velocity)) = impulses.get_mut(event.entity) else { continue };
         // if velocity.linvel.length_squared() > 60.0 { continue }
-        let force = -event.delta * 1_500_000.0;
-        impulse.impulse = force;
+        impulse.impulse = fling_impulse(event.delta);
     }
 }
+
+// A drag pulls away from where the ball should go, like a slingshot.
+pub fn fling_impulse(delta: Vec2) -> Vec2 {
+    -delta.clamp_length_max(MAX_PULL) * FLING_IMPULSE
+}
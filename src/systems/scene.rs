@@ -1,49 +1,51 @@
 use bevy::prelude::*;
-use bevy_rapier2d::prelude::*;
 use bevy::reflect::TypePath;
 use bevy::render::render_resource::{AsBindGroup, ShaderRef};
 use bevy::sprite::Material2d;
 use crate::vec2;
+use crate::game_state::*;
+use crate::assets::*;
+use crate::camera::*;
+use crate::level::*;
+use crate::map::*;
 
-const GROUND_SIZE: f32 = 750.0;
-const GROUND_RATIO: f32 = 1727.0 / 599.0;
-pub fn setup_scene(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>, mut materials: ResMut<Assets<CustomMaterial>>, asset_server: Res<AssetServer>)
-{
-    commands.spawn(Camera2d::default());
-    commands.spawn((PointLight::default(), Transform::from_xyz(4.0, 8.0, 4.0)));
-    commands
-        .spawn(Collider::cuboid(500.0, 25.0))
-        .insert(Transform::from_xyz(0.0, -25.0, 0.0))
-        .insert(CollisionGroups::new(
-            Group::GROUP_1,
-            Group::GROUP_1 | Group::GROUP_2 | Group::GROUP_3 | Group::GROUP_4,
-        ));
-    commands
-        .spawn(Collider::cuboid(25.0, 10000.0))
-        .insert(Transform::from_xyz(-400.0, 4000.0, 0.0))
-        .insert(CollisionGroups::new(
-            Group::GROUP_1,
-            Group::GROUP_1 | Group::GROUP_2 | Group::GROUP_3 | Group::GROUP_4,
-        ));
-    commands
-        .spawn(Collider::cuboid(25.0, 10000.0))
-        .insert(Transform::from_xyz(400.0, 4000.0, 0.0))
-        .insert(CollisionGroups::new(
-            Group::GROUP_1,
-            Group::GROUP_1 | Group::GROUP_2 | Group::GROUP_3 | Group::GROUP_4,
-        ));
+// Swimmable area of the level, kept up to date by whatever builds the level.
+#[derive(Resource)]
+pub struct LevelBounds {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl LevelBounds {
+    // The shaft is built upwards from its entrance at `min.y`, so depth grows with y.
+    pub fn depth_at(&self, y: f32) -> f32 {
+        (y - self.min.y).max(0.0)
+    }
+}
+
+pub fn setup_camera(mut commands: Commands) {
+    commands.spawn((Camera2d, CameraRig::default()));
+}
+
+pub fn setup_scene(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<CustomMaterial>>,
+    maps: Res<Assets<LevelMap>>,
+    levels: Res<LevelAssets>,
+) {
+    let Some(map) = maps.get(&levels.start) else { return };
+    commands.insert_resource(LevelBounds { min: vec2!(-map.size.x / 2.0, 0.0), max: vec2!(map.size.x / 2.0, map.depth) });
+    // Above the room, the shaft walls are streamed in around the camera by `stream_level_system`.
+    commands.insert_resource(Level::new(level_seed(), map.size.y));
+    commands.spawn((PointLight::default(), Transform::from_xyz(4.0, 8.0, 4.0), StateScoped(InGame)));
+    spawn_level_map(&mut commands, map);
     commands.spawn((
         Mesh2d(meshes.add(Rectangle::default())),
         MeshMaterial2d(materials.add(CustomMaterial {})),
         Transform::from_xyz(0.0, -0.5, 0.0).with_scale(Vec3 { x: 100.0, y: 100.0, z: 100.0 }),
+        StateScoped(InGame),
     ));
-    
-    commands
-        .spawn(Sprite {
-            image: asset_server.load("textures/ground.png"),
-            custom_size: Some(vec2!(GROUND_RATIO * GROUND_SIZE, GROUND_SIZE)),
-            ..default()
-        });
 }
 
 const SHADER_ASSET_PATH: &str = "shaders/background.wgsl";
@@ -0,0 +1,162 @@
+use std::ops::Range;
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use crate::enemy::*;
+use crate::player::*;
+use crate::scene::*;
+use crate::assets::*;
+use crate::layers::*;
+
+pub struct SpawnBand {
+    pub depth: Range<f32>,
+    pub max_alive: usize,
+    pub interval: f32,
+    // Fish kinds with their relative weights.
+    pub table: Vec<(FishKind, u32)>,
+}
+
+#[derive(Resource)]
+pub struct SpawnTable {
+    pub bands: Vec<SpawnBand>,
+    // Distance kept between a spawn point and the edge of the screen.
+    pub margin: f32,
+    pub despawn_distance: f32,
+}
+
+impl SpawnTable {
+    pub fn band_at(&self, depth: f32) -> Option<&SpawnBand> {
+        self.bands.iter().find(|band| band.depth.contains(&depth))
+    }
+}
+
+impl Default for SpawnTable {
+    fn default() -> Self {
+        Self {
+            bands: vec![
+                SpawnBand { depth: 0.0..1500.0, max_alive: 2, interval: 4.0, table: vec![(FishKind::Player, 2), (FishKind::Ball, 1)] },
+                SpawnBand { depth: 1500.0..5000.0, max_alive: 4, interval: 3.0, table: vec![(FishKind::Player, 3), (FishKind::Ball, 1)] },
+                SpawnBand { depth: 5000.0..f32::INFINITY, max_alive: 7, interval: 2.0, table: vec![(FishKind::Player, 2), (FishKind::Ball, 2)] },
+            ],
+            margin: 150.0,
+            despawn_distance: 2500.0,
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct Spawner {
+    pub elapsed: f32,
+    pub rng: u32,
+}
+
+impl Default for Spawner {
+    fn default() -> Self {
+        Self { elapsed: 0.0, rng: 0x9E37_79B9 }
+    }
+}
+
+impl Spawner {
+    // xorshift32, good enough to scatter spawns.
+    fn next_u32(&mut self) -> u32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng
+    }
+
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1 << 24) as f32
+    }
+
+    fn pick(&mut self, table: &[(FishKind, u32)]) -> Option<FishKind> {
+        let total: u32 = table.iter().map(|(_, weight)| weight).sum();
+        if total == 0 { return None }
+        let mut roll = self.next_u32() % total;
+        table.iter().find(|(_, weight)| {
+            if roll < *weight { return true }
+            roll -= weight;
+            false
+        }).map(|(kind, _)| *kind)
+    }
+}
+
+#[derive(Component)]
+pub struct Spawned;
+
+const SPAWN_ATTEMPTS: usize = 8;
+const SPAWN_CLEARANCE: f32 = 80.0;
+
+pub fn spawn_enemies_system(
+    mut commands: Commands,
+    textures: Res<TextureAssets>,
+    animations: Res<AnimationAssets>,
+    time: Res<Time>,
+    table: Res<SpawnTable>,
+    bounds: Res<LevelBounds>,
+    mut spawner: ResMut<Spawner>,
+    rapier: ReadRapierContext,
+    player: Query<&GlobalTransform, With<Player>>,
+    camera: Query<(&GlobalTransform, &OrthographicProjection), With<Camera2d>>,
+    alive: Query<(), With<Spawned>>,
+) {
+    let Ok(player) = player.get_single() else { return };
+    let Ok((camera, projection)) = camera.get_single() else { return };
+    let Some(band) = table.band_at(bounds.depth_at(player.translation().y)) else { return };
+
+    spawner.elapsed += time.delta_secs();
+    if spawner.elapsed < band.interval { return }
+    spawner.elapsed = 0.0;
+    if alive.iter().count() >= band.max_alive { return }
+    let Some(kind) = spawner.pick(&band.table) else { return };
+
+    let center = camera.translation().truncate();
+    let view = Rect::from_corners(projection.area.min + center, projection.area.max + center);
+    let context = rapier.single();
+    let walls = Layer::World.query();
+
+    for _ in 0..SPAWN_ATTEMPTS {
+        // The shaft is narrower than the screen, so spawn above or below the view.
+        let y = if spawner.next_f32() < 0.5 { view.max.y + table.margin } else { view.min.y - table.margin };
+        let x = bounds.min.x.lerp(bounds.max.x, spawner.next_f32());
+        if y < bounds.min.y || y > bounds.max.y { continue }
+
+        let mut blocked = false;
+        context.intersections_with_shape(Vec2::new(x, y), 0.0, &Collider::ball(SPAWN_CLEARANCE), walls, |_| {
+            blocked = true;
+            false
+        });
+        if blocked { continue }
+
+        let fish = spawn_fish(&mut commands, &textures, &animations, kind, Vec2::new(x, y));
+        commands.entity(fish).insert(Spawned);
+        return;
+    }
+}
+
+pub fn despawn_far_enemies_system(
+    mut commands: Commands,
+    table: Res<SpawnTable>,
+    player: Query<&GlobalTransform, With<Player>>,
+    fish: Query<(Entity, &GlobalTransform), With<Spawned>>,
+) {
+    let Ok(player) = player.get_single() else { return };
+    let player = player.translation().truncate();
+    for (entity, transform) in &fish {
+        if transform.translation().truncate().distance(player) < table.despawn_distance { continue }
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deeper_in_the_shaft_picks_a_deeper_band() {
+        let bounds = LevelBounds { min: Vec2::new(-375.0, 0.0), max: Vec2::new(375.0, 14000.0) };
+        let table = SpawnTable::default();
+        let shallow = table.band_at(bounds.depth_at(100.0)).unwrap();
+        let deep = table.band_at(bounds.depth_at(6000.0)).unwrap();
+        assert!(deep.depth.start > shallow.depth.start);
+    }
+}
@@ -0,0 +1,243 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use crate::animation::*;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum AnimationCondition {
+    SpeedAbove(f32),
+    SpeedBelow(f32),
+    Moving,
+    NotMoving,
+    Trigger(&'static str),
+    // The current clip ended, or finished a cycle if it loops.
+    Finished,
+}
+
+pub struct Transition<T> {
+    pub from: Option<T>,
+    pub to: T,
+    pub via: Option<T>,
+    pub conditions: Vec<AnimationCondition>,
+    pub priority: i32,
+}
+
+impl<T> Transition<T> {
+    pub fn to(to: T) -> Self {
+        Self { from: None, to, via: None, conditions: Vec::new(), priority: 0 }
+    }
+
+    pub fn from(mut self, from: T) -> Self {
+        self.from = Some(from);
+        self
+    }
+
+    // Clip played once before entering `to`, looping clips for a single cycle.
+    pub fn via(mut self, via: T) -> Self {
+        self.via = Some(via);
+        self
+    }
+
+    pub fn when(mut self, condition: AnimationCondition) -> Self {
+        self.conditions.push(condition);
+        self
+    }
+
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+}
+
+// Written by gameplay systems, read by the state machine on the same entity.
+#[derive(Component, Default)]
+pub struct AnimationParams {
+    pub intent: Vec2,
+    pub triggers: Vec<&'static str>,
+}
+
+impl AnimationParams {
+    pub fn trigger(&mut self, name: &'static str) {
+        self.triggers.push(name);
+    }
+}
+
+#[derive(Component)]
+#[require(AnimationParams)]
+pub struct AnimationStateMachine<T> {
+    pub state: T,
+    pub pending: Option<T>,
+    pub elapsed: f32,
+    pub transitions: Vec<Transition<T>>,
+    pub dwell: Vec<(T, f32)>,
+}
+
+impl<T: AnimationKey> AnimationStateMachine<T> {
+    pub fn new(state: T) -> Self {
+        Self { state, pending: None, elapsed: 0.0, transitions: Vec::new(), dwell: Vec::new() }
+    }
+
+    pub fn transition(mut self, transition: Transition<T>) -> Self {
+        self.transitions.push(transition);
+        self.transitions.sort_by_key(|t| -t.priority);
+        self
+    }
+
+    // Minimum time spent in `state` before any transition out of it is considered.
+    pub fn min_dwell(mut self, state: T, seconds: f32) -> Self {
+        self.dwell.push((state, seconds));
+        self
+    }
+
+    fn dwell_for(&self, state: T) -> f32 {
+        self.dwell.iter().find(|(s, _)| *s == state).map_or(0.0, |(_, d)| *d)
+    }
+
+    fn enter(&mut self, animator: &mut Animator<T>, state: T) {
+        self.state = state;
+        self.pending = None;
+        self.elapsed = 0.0;
+        // A `via` clip can be the one already playing, start it over so it can finish again.
+        if animator.current == state { animator.playing = None }
+        animator.current = state;
+    }
+}
+
+fn condition_met(condition: AnimationCondition, params: &AnimationParams, finished: bool, speed: f32) -> bool {
+    match condition {
+        AnimationCondition::SpeedAbove(threshold) => speed > threshold,
+        AnimationCondition::SpeedBelow(threshold) => speed <= threshold,
+        AnimationCondition::Moving => params.intent.length() > 0.1,
+        AnimationCondition::NotMoving => params.intent.length() <= 0.1,
+        AnimationCondition::Trigger(name) => params.triggers.contains(&name),
+        AnimationCondition::Finished => finished,
+    }
+}
+
+pub fn update_animation_state<T>(
+    time: Res<Time>,
+    mut finished_events: EventReader<AnimationFinished<T>>,
+    velocities: Query<&Velocity>,
+    mut query: Query<(Entity, &mut AnimationStateMachine<T>, &mut Animator<T>, &mut AnimationParams, Option<&Parent>)>,
+) where T: AnimationKey {
+    let finished: Vec<(Entity, T)> = finished_events.read().map(|e| (e.entity, e.clip)).collect();
+    for (entity, mut machine, mut animator, mut params, parent) in &mut query {
+        machine.elapsed += time.delta_secs();
+        let clip_finished = finished.contains(&(entity, animator.current));
+
+        if let Some(target) = machine.pending {
+            if clip_finished { machine.enter(&mut animator, target) }
+            params.triggers.clear();
+            continue;
+        }
+
+        if machine.elapsed < machine.dwell_for(machine.state) {
+            params.triggers.clear();
+            continue;
+        }
+
+        let speed = body_speed(entity, parent, &velocities);
+
+        let state = machine.state;
+        let chosen = machine.transitions.iter()
+            // Going through a `via` clip is a change even when it comes back to the same state.
+            .filter(|t| (t.to != state || t.via.is_some()) && t.from.is_none_or(|from| from == state))
+            .find(|t| t.conditions.iter().all(|c| condition_met(*c, &params, clip_finished, speed)))
+            .map(|t| (t.to, t.via));
+        params.triggers.clear();
+
+        let Some((to, via)) = chosen else { continue };
+        let Some(via) = via else {
+            machine.enter(&mut animator, to);
+            continue;
+        };
+        machine.enter(&mut animator, via);
+        machine.pending = Some(to);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+    use std::time::Duration;
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    enum Key { Idle, Swim, Turn }
+
+    impl AnimationKey for Key {
+        fn clip_name(&self) -> &'static str {
+            match self {
+                Key::Idle => "Idle",
+                Key::Swim => "Swim",
+                Key::Turn => "Turn",
+            }
+        }
+    }
+
+    fn world(machine: AnimationStateMachine<Key>) -> (World, Entity) {
+        let mut world = World::new();
+        world.init_resource::<Time>();
+        world.init_resource::<Events<AnimationFinished<Key>>>();
+        let state = machine.state;
+        let entity = world.spawn((machine, Animator::from_sheet(Handle::default(), state))).id();
+        (world, entity)
+    }
+
+    // Runs one frame after `seconds`, with `trigger` set by gameplay, and returns the new state.
+    fn step(world: &mut World, entity: Entity, seconds: f32, trigger: Option<&'static str>) -> Key {
+        world.resource_mut::<Time>().advance_by(Duration::from_secs_f32(seconds));
+        if let Some(trigger) = trigger { world.get_mut::<AnimationParams>(entity).unwrap().trigger(trigger) }
+        world.run_system_once(update_animation_state::<Key>).unwrap();
+        world.get::<AnimationStateMachine<Key>>(entity).unwrap().state
+    }
+
+    fn finish_clip(world: &mut World, entity: Entity) {
+        let clip = world.get::<Animator<Key>>(entity).unwrap().current;
+        world.send_event(AnimationFinished { entity, clip });
+    }
+
+    #[test]
+    fn dwell_time_holds_the_state() {
+        let machine = AnimationStateMachine::new(Key::Idle)
+            .transition(Transition::to(Key::Swim).when(AnimationCondition::Trigger("go")))
+            .min_dwell(Key::Idle, 0.5);
+        let (mut world, entity) = world(machine);
+
+        assert_eq!(step(&mut world, entity, 0.2, Some("go")), Key::Idle);
+        assert_eq!(step(&mut world, entity, 0.4, Some("go")), Key::Swim);
+    }
+
+    #[test]
+    fn higher_priority_transition_wins() {
+        let machine = AnimationStateMachine::new(Key::Idle)
+            .transition(Transition::to(Key::Swim).when(AnimationCondition::Trigger("go")))
+            .transition(Transition::to(Key::Turn).when(AnimationCondition::Trigger("go")).priority(1));
+        let (mut world, entity) = world(machine);
+
+        assert_eq!(step(&mut world, entity, 0.1, Some("go")), Key::Turn);
+    }
+
+    #[test]
+    fn via_clip_hands_over_once_it_finishes() {
+        let machine = AnimationStateMachine::new(Key::Idle)
+            .transition(Transition::to(Key::Swim).via(Key::Turn).when(AnimationCondition::Trigger("go")));
+        let (mut world, entity) = world(machine);
+
+        assert_eq!(step(&mut world, entity, 0.1, Some("go")), Key::Turn);
+        assert_eq!(step(&mut world, entity, 0.1, None), Key::Turn);
+        finish_clip(&mut world, entity);
+        assert_eq!(step(&mut world, entity, 0.1, None), Key::Swim);
+        assert_eq!(world.get::<Animator<Key>>(entity).unwrap().current, Key::Swim);
+    }
+
+    #[test]
+    fn via_clip_can_return_to_the_current_state() {
+        let machine = AnimationStateMachine::new(Key::Idle)
+            .transition(Transition::to(Key::Idle).via(Key::Turn).when(AnimationCondition::Trigger("hit")));
+        let (mut world, entity) = world(machine);
+
+        assert_eq!(step(&mut world, entity, 0.1, Some("hit")), Key::Turn);
+        finish_clip(&mut world, entity);
+        assert_eq!(step(&mut world, entity, 0.1, None), Key::Idle);
+    }
+}
@@ -0,0 +1,183 @@
+use std::marker::PhantomData;
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use noisy_bevy::simplex_noise_2d;
+use std::f32::consts::{FRAC_PI_2, TAU};
+use crate::perception::*;
+
+#[derive(Clone, Copy)]
+pub enum Behaviour {
+    Seek,
+    Flee,
+    // Slows down linearly inside `slowing_radius`.
+    Arrive { slowing_radius: f32 },
+    // Seeks where the target will be, looking at most `max_prediction` seconds ahead.
+    Pursue { max_prediction: f32 },
+    // Circles the target counter-clockwise at `radius`.
+    Orbit { radius: f32 },
+    // Drifts around, ignores the target.
+    Wander { frequency: f32 },
+}
+
+#[derive(Component)]
+pub struct Steering {
+    pub target: Option<Entity>,
+    // Static goal used when there is no target entity.
+    pub point: Option<Vec2>,
+    pub behaviours: Vec<(Behaviour, f32)>,
+    pub max_force: f32,
+    pub turn_rate: f32,
+}
+
+impl Steering {
+    pub fn new(max_force: f32) -> Self {
+        Self { target: None, point: None, behaviours: Vec::new(), max_force, turn_rate: 5.0 }
+    }
+
+    pub fn behaviour(mut self, behaviour: Behaviour, weight: f32) -> Self {
+        self.behaviours.push((behaviour, weight));
+        self
+    }
+
+    pub fn turn_rate(mut self, turn_rate: f32) -> Self {
+        self.turn_rate = turn_rate;
+        self
+    }
+}
+
+// Points `Steering::target` at the entity carrying `M` while it has none.
+// Agents with a `Perception` only get it as prey and have to spot it first.
+#[derive(Component)]
+pub struct AcquireTarget<M>(PhantomData<M>);
+
+// Seconds left before the agent can steer, look around or attack again.
+#[derive(Component)]
+pub struct Stunned(pub f32);
+
+impl<M> Default for AcquireTarget<M> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+pub fn acquire_target_system<M: Component>(
+    targets: Query<Entity, With<M>>,
+    mut blind: Query<&mut Steering, (With<AcquireTarget<M>>, Without<Perception>)>,
+    mut seeing: Query<&mut Perception, With<AcquireTarget<M>>>,
+) {
+    let Ok(target) = targets.get_single() else { return };
+    for mut steering in &mut blind {
+        if steering.target.is_none() { steering.target = Some(target) }
+    }
+    for mut perception in &mut seeing {
+        if perception.prey.is_none() { perception.prey = Some(target) }
+    }
+}
+
+struct Body {
+    position: Vec2,
+    velocity: Vec2,
+}
+
+// `seed` is (elapsed seconds, per-agent offset) so wandering agents don't move in lockstep.
+fn desired_direction(behaviour: Behaviour, agent: &Body, target: Option<&Body>, seed: Vec2) -> Vec2 {
+    let offset = target.map_or(Vec2::ZERO, |target| target.position - agent.position);
+    match (behaviour, target) {
+        (Behaviour::Wander { frequency }, _) => Vec2::from_angle(simplex_noise_2d(Vec2::new(seed.x * frequency, seed.y)) * TAU),
+        // Everything else steers relative to a target.
+        (_, None) => Vec2::ZERO,
+        (Behaviour::Seek, _) => offset.normalize_or_zero(),
+        (Behaviour::Flee, _) => -offset.normalize_or_zero(),
+        (Behaviour::Arrive { slowing_radius }, _) => offset.normalize_or_zero() * (offset.length() / slowing_radius).min(1.0),
+        (Behaviour::Pursue { max_prediction }, Some(target)) => {
+            let speed = agent.velocity.length();
+            let lookahead = if speed > 0.0 { (offset.length() / speed).min(max_prediction) } else { max_prediction };
+            (offset + target.velocity * lookahead).normalize_or_zero()
+        }
+        (Behaviour::Orbit { radius }, _) => {
+            let radial = -offset.normalize_or_zero();
+            let correction = (radius - offset.length()) / radius;
+            (radial.perp() + radial * correction).normalize_or_zero()
+        }
+    }
+}
+
+pub fn steering_system(
+    time: Res<Time>,
+    targets: Query<(&GlobalTransform, Option<&Velocity>)>,
+    mut agents: Query<(Entity, &Steering, &mut Transform, &mut ExternalForce, Option<&Velocity>), Without<Stunned>>,
+) {
+    for (entity, steering, mut transform, mut force, velocity) in &mut agents {
+        let agent = Body {
+            position: transform.translation.truncate(),
+            velocity: velocity.map_or(Vec2::ZERO, |v| v.linvel),
+        };
+        let target = steering.target
+            .and_then(|t| targets.get(t).ok())
+            .map(|(transform, velocity)| Body {
+                position: transform.translation().truncate(),
+                velocity: velocity.map_or(Vec2::ZERO, |v| v.linvel),
+            })
+            .or(steering.point.map(|position| Body { position, velocity: Vec2::ZERO }));
+        let seed = Vec2::new(time.elapsed_secs(), entity.index() as f32 * 7.31);
+
+        let direction = steering.behaviours.iter()
+            .map(|(behaviour, weight)| desired_direction(*behaviour, &agent, target.as_ref(), seed) * *weight)
+            .sum::<Vec2>()
+            .clamp_length_max(1.0);
+        force.force = direction * steering.max_force;
+
+        if direction.length_squared() < 1e-4 { continue }
+        let target_angle = direction.y.atan2(direction.x) - FRAC_PI_2;
+        let target_rotation = Quat::from_rotation_z(target_angle);
+        transform.rotation = transform.rotation.slerp(target_rotation, steering.turn_rate * time.delta_secs());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AGENT: Body = Body { position: Vec2::ZERO, velocity: Vec2::ZERO };
+
+    fn towards(behaviour: Behaviour, target: Body) -> Vec2 {
+        desired_direction(behaviour, &AGENT, Some(&target), Vec2::ZERO)
+    }
+
+    #[test]
+    fn seek_heads_for_the_target() {
+        assert_eq!(towards(Behaviour::Seek, Body { position: Vec2::new(0.0, 50.0), velocity: Vec2::ZERO }), Vec2::Y);
+    }
+
+    #[test]
+    fn flee_heads_away_from_the_target() {
+        assert_eq!(towards(Behaviour::Flee, Body { position: Vec2::new(0.0, 50.0), velocity: Vec2::ZERO }), -Vec2::Y);
+    }
+
+    #[test]
+    fn arrive_slows_down_inside_the_radius() {
+        let behaviour = Behaviour::Arrive { slowing_radius: 100.0 };
+        assert_eq!(towards(behaviour, Body { position: Vec2::new(300.0, 0.0), velocity: Vec2::ZERO }), Vec2::X);
+        assert_eq!(towards(behaviour, Body { position: Vec2::new(25.0, 0.0), velocity: Vec2::ZERO }), Vec2::X * 0.25);
+    }
+
+    #[test]
+    fn pursue_leads_a_moving_target() {
+        let direction = towards(Behaviour::Pursue { max_prediction: 1.0 }, Body { position: Vec2::new(100.0, 0.0), velocity: Vec2::new(0.0, 100.0) });
+        assert!((direction - Vec2::new(1.0, 1.0).normalize()).length() < 1e-5);
+    }
+
+    #[test]
+    fn orbit_circles_the_target_at_its_radius() {
+        // On the circle it only moves along it, counter-clockwise around the target.
+        let direction = towards(Behaviour::Orbit { radius: 100.0 }, Body { position: Vec2::new(100.0, 0.0), velocity: Vec2::ZERO });
+        assert!((direction - -Vec2::Y).length() < 1e-5);
+    }
+
+    #[test]
+    fn wander_does_not_need_a_target() {
+        let direction = desired_direction(Behaviour::Wander { frequency: 0.5 }, &AGENT, None, Vec2::new(3.0, 1.0));
+        assert!((direction.length() - 1.0).abs() < 1e-5);
+        assert_eq!(desired_direction(Behaviour::Seek, &AGENT, None, Vec2::ZERO), Vec2::ZERO);
+    }
+}
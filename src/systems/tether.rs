@@ -0,0 +1,185 @@
+use bevy::prelude::*;
+use bevy::render::mesh::PrimitiveTopology;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::view::NoFrustumCulling;
+use bevy_rapier2d::prelude::*;
+use crate::player::*;
+use crate::game_state::*;
+use crate::input::*;
+use crate::layers::*;
+
+const RING_RADIUS: f32 = 7.0;
+const ROPE_WIDTH: f32 = 6.0;
+const ROPE_COLOR: Color = Color::srgb(0.45, 0.33, 0.2);
+
+#[derive(Resource)]
+pub struct TetherConfig {
+    // Free-swinging rings between the diver and the ball.
+    pub segments: usize,
+    pub length: f32,
+    pub min_length: f32,
+    pub max_length: f32,
+    // Rope length reeled per second.
+    pub reel_speed: f32,
+    // Impulse a single link can take in one physics step before the rope snaps.
+    pub break_impulse: f32,
+}
+
+impl Default for TetherConfig {
+    fn default() -> Self {
+        Self {
+            segments: 5,
+            length: 200.0,
+            min_length: 60.0,
+            max_length: 600.0,
+            reel_speed: 150.0,
+            break_impulse: 5_000_000.0,
+        }
+    }
+}
+
+// Lives on the rope mesh. `links` are the rings then the ball, each jointed to the one before it.
+#[derive(Component)]
+pub struct Tether {
+    pub player: Entity,
+    pub links: Vec<Entity>,
+    pub ball_anchor: Vec2,
+    pub length: f32,
+}
+
+impl Tether {
+    fn segment_length(&self) -> f32 {
+        self.length / self.links.len() as f32
+    }
+}
+
+pub fn spawn_tether(
+    commands: &mut Commands,
+    config: &TetherConfig,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<ColorMaterial>,
+    (player, player_position): (Entity, Vec2),
+    (ball, ball_position): (Entity, Vec2),
+) {
+    let ball_anchor = Vec2::new(0.0, BALL_RADIUS);
+    let segment = config.length / (config.segments + 1) as f32;
+    let mut links = Vec::with_capacity(config.segments + 1);
+    let mut previous = player;
+
+    for i in 0..config.segments {
+        let t = (i + 1) as f32 / (config.segments + 1) as f32;
+        let position = player_position.lerp(ball_position + ball_anchor, t);
+        previous = commands
+            .spawn(RigidBody::Dynamic)
+            .insert(Ring)
+            .insert(StateScoped(InGame))
+            .insert(Layer::RopeSegment.groups())
+            .insert(GravityScale(1.0))
+            .insert(AdditionalMassProperties::Mass(1000.0))
+            .insert(Collider::ball(RING_RADIUS))
+            .insert(Transform::from_translation(position.extend(0.0)))
+            .insert(ImpulseJoint::new(previous, RopeJointBuilder::new(segment)))
+            .id();
+        links.push(previous);
+    }
+
+    commands.entity(ball).insert(ImpulseJoint::new(
+        previous,
+        RopeJointBuilder::new(segment).local_anchor2(ball_anchor),
+    ));
+    links.push(ball);
+
+    let mesh = Mesh::new(PrimitiveTopology::TriangleStrip, RenderAssetUsages::default());
+    commands.spawn((
+        Tether { player, links, ball_anchor, length: config.length },
+        Mesh2d(meshes.add(mesh)),
+        MeshMaterial2d(materials.add(ROPE_COLOR)),
+        // The strip is rebuilt every frame, so its spawn-time bounds mean nothing.
+        NoFrustumCulling,
+        // Behind the diver and the ball at 0, in front of the ground at -0.1 and the walls at -0.2.
+        Transform::from_xyz(0.0, 0.0, -0.05),
+        StateScoped(InGame),
+    ));
+}
+
+pub fn reel_tether_system(
+    actions: PlayerActions,
+    config: Res<TetherConfig>,
+    time: Res<Time>,
+    mut tethers: Query<&mut Tether>,
+    mut joints: Query<&mut ImpulseJoint>,
+) {
+    let direction = actions.value::<ReelOut>().as_bool() as i32 as f32 - actions.value::<ReelIn>().as_bool() as i32 as f32;
+    if direction == 0.0 { return }
+
+    for mut tether in &mut tethers {
+        let length = (tether.length + direction * config.reel_speed * time.delta_secs()).clamp(config.min_length, config.max_length);
+        if length == tether.length { continue }
+        tether.length = length;
+
+        let segment = tether.segment_length();
+        for link in &tether.links {
+            let Ok(mut joint) = joints.get_mut(*link) else { continue };
+            if let TypedJoint::RopeJoint(rope) = &mut joint.data { rope.set_max_distance(segment); }
+        }
+    }
+}
+
+pub fn tether_break_system(
+    mut commands: Commands,
+    config: Res<TetherConfig>,
+    contexts: Query<&RapierContextJoints, With<DefaultRapierContext>>,
+    tethers: Query<(Entity, &Tether)>,
+) {
+    let Ok(context) = contexts.get_single() else { return };
+    for (entity, tether) in &tethers {
+        let strongest = tether.links.iter()
+            .filter_map(|link| context.entity2impulse_joint().get(link))
+            .filter_map(|handle| context.impulse_joints.get(*handle))
+            .map(|joint| Vec2::new(joint.impulses.x, joint.impulses.y).length())
+            .fold(0.0, f32::max);
+        if strongest < config.break_impulse { continue }
+
+        info!("tether snapped under an impulse of {strongest:.0}");
+        let (ball, rings) = tether.links.split_last().unwrap();
+        commands.entity(*ball).remove::<ImpulseJoint>();
+        for ring in rings { commands.entity(*ring).despawn(); }
+        commands.entity(entity).despawn();
+    }
+}
+
+pub fn draw_tether_system(
+    tethers: Query<(&Tether, &Mesh2d)>,
+    transforms: Query<&GlobalTransform>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    for (tether, mesh) in &tethers {
+        let Ok(player) = transforms.get(tether.player) else { continue };
+        let Some((ball, rings)) = tether.links.split_last() else { continue };
+        let Ok(ball) = transforms.get(*ball) else { continue };
+
+        let mut points = vec![player.translation().truncate()];
+        points.extend(rings.iter().filter_map(|ring| transforms.get(*ring).ok()).map(|ring| ring.translation().truncate()));
+        points.push(ball.transform_point(tether.ball_anchor.extend(0.0)).truncate());
+
+        // A strip of quads following the chain, as wide as the rope on both sides of it.
+        let mut positions = Vec::with_capacity(points.len() * 2);
+        let mut uvs = Vec::with_capacity(points.len() * 2);
+        let mut travelled = 0.0;
+        for i in 0..points.len() {
+            let before = points[i.saturating_sub(1)];
+            let after = points[(i + 1).min(points.len() - 1)];
+            let side = (after - before).normalize_or_zero().perp() * ROPE_WIDTH * 0.5;
+            if i > 0 { travelled += points[i].distance(points[i - 1]); }
+            positions.push((points[i] + side).extend(0.0).to_array());
+            positions.push((points[i] - side).extend(0.0).to_array());
+            uvs.push([travelled / ROPE_WIDTH, 0.0]);
+            uvs.push([travelled / ROPE_WIDTH, 1.0]);
+        }
+
+        let Some(mesh) = meshes.get_mut(&mesh.0) else { continue };
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0, 0.0, 1.0]; positions.len()]);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::HashSet;
+use bevy::prelude::*;
+use bevy::ecs::system::SystemParam;
+use bevy_rapier2d::prelude::*;
+use bevy_rapier2d::rapier::prelude::{ColliderHandle, PhysicsPipeline};
+use std::f32::consts::TAU;
+use crate::hover::*;
+use crate::player::*;
+
+// Seconds of flight drawn by the preview.
+const PREVIEW_TIME: f32 = 1.5;
+// Matches Rapier's default 60 Hz step.
+const PREVIEW_STEP: f32 = 1.0 / 60.0;
+const PREVIEW_STEPS: usize = (PREVIEW_TIME / PREVIEW_STEP) as usize;
+const WEAK_COLOR: Color = Color::srgb(0.2, 0.7, 0.3);
+const STRONG_COLOR: Color = Color::srgb(0.9, 0.2, 0.1);
+
+// Pull currently applied to the ball, from a mouse, finger or charging gamepad.
+fn pending_pull(
+    drag_state: &DragState,
+    fling: &GamepadFling,
+    touches: &Touches,
+    windows: &Query<&Window>,
+    camera_q: &Query<(&Camera, &GlobalTransform)>,
+) -> Option<Vec2> {
+    if let (Some(_), Some(start)) = (drag_state.active_entity, drag_state.drag_start) {
+        let screen = match drag_state.touch {
+            Some(id) => touches.get_pressed(id).map(|touch| touch.position()),
+            None => windows.get_single().ok().and_then(|window| window.cursor_position()),
+        }?;
+        let (camera, camera_transform) = camera_q.get_single().ok()?;
+        let end = camera.viewport_to_world_2d(camera_transform, screen).ok()?;
+        return Some(end - start);
+    }
+    (fling.charge > 0.0 && fling.aim != Vec2::ZERO).then(|| -fling.aim * fling.charge * MAX_PULL)
+}
+
+#[allow(dead_code)]
+pub struct PredictedContact {
+    pub entity: Entity,
+    // Where the body was when it first touched.
+    pub position: Vec2,
+    pub step: usize,
+}
+
+pub struct Prediction {
+    // Body position after each step, starting with the current one.
+    pub path: Vec<Vec2>,
+    pub contact: Option<PredictedContact>,
+}
+
+// Runs a copy of the Rapier world forward, so joints, collision groups and every other body
+// behave exactly like the real step. The live world is left untouched.
+#[derive(SystemParam)]
+pub struct TrajectoryPredictor<'w, 's> {
+    context: Query<'w, 's, (
+        &'static RapierContextSimulation,
+        &'static RapierContextColliders,
+        &'static RapierContextJoints,
+        &'static RapierRigidBodySet,
+        &'static RapierConfiguration,
+    ), With<DefaultRapierContext>>,
+}
+
+impl TrajectoryPredictor<'_, '_> {
+    pub fn predict(&self, body: Entity, impulse: Vec2, steps: usize) -> Option<Prediction> {
+        let (simulation, colliders, joints, bodies, config) = self.context.get_single().ok()?;
+        let handle = *bodies.entity2body().get(&body)?;
+
+        let mut islands = simulation.islands.clone();
+        let mut broad_phase = simulation.broad_phase.clone();
+        let mut narrow_phase = simulation.narrow_phase.clone();
+        let mut ccd_solver = simulation.ccd_solver.clone();
+        let mut body_set = bodies.bodies.clone();
+        let mut collider_set = colliders.colliders.clone();
+        let mut impulse_joints = joints.impulse_joints.clone();
+        let mut multibody_joints = joints.multibody_joints.clone();
+        let mut pipeline = PhysicsPipeline::new();
+        let mut parameters = simulation.integration_parameters;
+        parameters.dt = PREVIEW_STEP;
+
+        let own_colliders = body_set.get(handle)?.colliders().to_vec();
+        let touching = |narrow_phase: &bevy_rapier2d::rapier::prelude::NarrowPhase| -> Vec<ColliderHandle> {
+            own_colliders.iter()
+                .flat_map(|collider| narrow_phase.contact_pairs_with(*collider)
+                    .filter(|pair| pair.has_any_active_contact)
+                    .map(move |pair| if pair.collider1 == *collider { pair.collider2 } else { pair.collider1 }))
+                .collect()
+        };
+        // Whatever it already rests against doesn't count as a hit.
+        let already_touching: HashSet<ColliderHandle> = touching(&narrow_phase).into_iter().collect();
+
+        body_set.get_mut(handle)?.apply_impulse(impulse.into(), true);
+        let mut path = vec![body_set.get(handle)?.translation().xy().into()];
+        let mut contact = None;
+        for step in 1..=steps {
+            pipeline.step(
+                &config.gravity.into(),
+                &parameters,
+                &mut islands,
+                &mut broad_phase,
+                &mut narrow_phase,
+                &mut body_set,
+                &mut collider_set,
+                &mut impulse_joints,
+                &mut multibody_joints,
+                &mut ccd_solver,
+                None,
+                &(),
+                &(),
+            );
+            let position: Vec2 = body_set.get(handle)?.translation().xy().into();
+            path.push(position);
+
+            let hit = touching(&narrow_phase).into_iter().find(|other| !already_touching.contains(other));
+            if let Some(entity) = hit.and_then(|other| colliders.collider_entity(other)) {
+                contact = Some(PredictedContact { entity, position, step });
+                break;
+            }
+        }
+        Some(Prediction { path, contact })
+    }
+}
+
+pub fn drag_preview_system(
+    mut gizmos: Gizmos,
+    drag_state: Res<DragState>,
+    fling: Res<GamepadFling>,
+    touches: Res<Touches>,
+    windows: Query<&Window>,
+    camera_q: Query<(&Camera, &GlobalTransform)>,
+    predictor: TrajectoryPredictor,
+    ball: Query<(Entity, &GlobalTransform), With<Ball>>,
+) {
+    let Ok((entity, transform)) = ball.get_single() else { return };
+    let Some(pull) = pending_pull(&drag_state, &fling, &touches, &windows, &camera_q) else { return };
+    let pull = pull.clamp_length_max(MAX_PULL);
+    let power = pull.length() / MAX_PULL;
+    let color = WEAK_COLOR.mix(&STRONG_COLOR, power);
+    let origin = transform.translation().truncate();
+
+    // Rubber band to the pull point and a ring that fills up with power.
+    gizmos.line_2d(origin, origin + pull, color);
+    gizmos.arc_2d(Isometry2d::from_translation(origin), power * TAU, BALL_RADIUS + 10.0, color);
+
+    let Some(prediction) = predictor.predict(entity, fling_impulse(pull), PREVIEW_STEPS) else { return };
+    if let Some(contact) = &prediction.contact { gizmos.circle_2d(Isometry2d::from_translation(contact.position), BALL_RADIUS, color); }
+    gizmos.linestrip_2d(prediction.path, color.with_alpha(0.6));
+}
@@ -1,14 +1,176 @@
 
 use bevy::prelude::*;
+use crate::player::*;
+use crate::health::*;
+use crate::game_state::*;
+use crate::assets::*;
+use crate::input::*;
+use iyes_progress::prelude::*;
 
-pub fn setup_ui(mut commands: Commands, asset_server: Res<AssetServer>)
+#[derive(Component)]
+pub struct HealthText;
+
+#[derive(Component)]
+pub struct LoadingBar;
+
+#[derive(Component)]
+pub struct BindingsText;
+
+pub fn setup_ui(mut commands: Commands, fonts: Res<FontAssets>)
 {
     let text_font = TextFont {
-        font: asset_server.load("fonts/JetBrainsMono-Regular.ttf"),
+        font: fonts.main.clone(),
         font_size: 25.0,
         ..default()
     };
-    commands.spawn(Node::default()).with_children(|parent| {
-        parent.spawn((Text::new("Hello from the UI!"), text_font, TextColor(Color::srgb(0.0, 0.0, 0.0))));
+    commands.spawn((Node::default(), StateScoped(InGame))).with_children(|parent| {
+        parent.spawn((Text::new(""), text_font, TextColor(Color::srgb(0.0, 0.0, 0.0)), HealthText));
+    });
+}
+
+pub fn update_health_text_system(
+    player: Query<&Health, (With<Player>, Changed<Health>)>,
+    mut text: Query<&mut Text, With<HealthText>>,
+) {
+    let Ok(health) = player.get_single() else { return };
+    for mut text in &mut text {
+        text.0 = format!("HP {:.0}/{:.0}", health.current, health.max);
+    }
+}
+
+fn spawn_screen<S: States>(commands: &mut Commands, font: Handle<Font>, state: S, title: &str, hint: &str) -> Entity {
+    commands
+        .spawn((
+            Node {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                row_gap: Val::Px(20.0),
+                ..default()
+            },
+            BackgroundColor(Color::srgba(1.0, 1.0, 1.0, 0.6)),
+            StateScoped(state),
+        ))
+        .with_children(|parent| {
+            parent.spawn((Text::new(title), TextFont { font: font.clone(), font_size: 60.0, ..default() }, TextColor(Color::BLACK)));
+            parent.spawn((Text::new(hint), TextFont { font, font_size: 25.0, ..default() }, TextColor(Color::BLACK)));
+        })
+        .id()
+}
+
+// Runs before the fonts are in, so it uses Bevy's built-in font.
+pub fn setup_loading_screen(mut commands: Commands) {
+    let screen = spawn_screen(&mut commands, Handle::default(), GameState::Loading, "Loading", "");
+    commands.entity(screen).with_children(|parent| {
+        parent
+            .spawn((
+                Node { width: Val::Px(400.0), height: Val::Px(20.0), border: UiRect::all(Val::Px(2.0)), ..default() },
+                BorderColor(Color::BLACK),
+            ))
+            .with_children(|parent| {
+                parent.spawn((Node { width: Val::Percent(0.0), height: Val::Percent(100.0), ..default() }, BackgroundColor(Color::BLACK), LoadingBar));
+            });
     });
-}
\ No newline at end of file
+}
+
+pub fn update_loading_bar_system(progress: Res<ProgressTracker<GameState>>, mut bar: Query<&mut Node, With<LoadingBar>>) {
+    let progress = progress.get_global_progress();
+    let fraction = if progress.total == 0 { 0.0 } else { f32::from(progress) };
+    for mut node in &mut bar {
+        node.width = Val::Percent(fraction * 100.0);
+    }
+}
+
+pub fn setup_loading_failed(mut commands: Commands) {
+    spawn_screen(&mut commands, Handle::default(), GameState::LoadingFailed, "Missing assets", "See the log for the file that failed to load");
+}
+
+pub fn setup_main_menu(mut commands: Commands, fonts: Res<FontAssets>) {
+    spawn_screen(&mut commands, fonts.main.clone(), GameState::MainMenu, "Depths", "Press Enter to dive");
+}
+
+pub fn setup_pause_menu(mut commands: Commands, fonts: Res<FontAssets>) {
+    let screen = spawn_screen(&mut commands, fonts.main.clone(), GameState::Paused, "Paused", "Press Pause to resume, 1-9 to rebind");
+    commands.entity(screen).with_children(|parent| {
+        parent.spawn((Text::new(""), TextFont { font: fonts.main.clone(), font_size: 20.0, ..default() }, TextColor(Color::BLACK), BindingsText));
+    });
+}
+
+pub fn update_bindings_text_system(
+    bindings: Res<InputBindings>,
+    rebinding: Res<Rebinding>,
+    mut text: Query<(&mut Text, Ref<BindingsText>)>,
+) {
+    for (mut text, marker) in &mut text {
+        if !marker.is_added() && !bindings.is_changed() && !rebinding.is_changed() { continue }
+        let lines: Vec<String> = BindingSlot::ALL.iter().enumerate().map(|(index, slot)| {
+            let inputs = match rebinding.0 == Some(*slot) {
+                true if slot.is_analog() => "pull a trigger...".to_string(),
+                true => "press a key or button...".to_string(),
+                false => bindings.slot(*slot).iter().map(|input| input.to_string()).collect::<Vec<_>>().join(", "),
+            };
+            format!("{} {}: {}", index + 1, slot.name(), inputs)
+        }).collect();
+        text.0 = lines.join("\n");
+    }
+}
+
+pub fn setup_game_over(mut commands: Commands, fonts: Res<FontAssets>) {
+    spawn_screen(&mut commands, fonts.main.clone(), GameState::GameOver, "You drowned", "Press R to dive again, Escape for the menu");
+}
+
+#[derive(Component)]
+pub struct JoystickBase;
+
+#[derive(Component)]
+pub struct JoystickKnob;
+
+const KNOB_SIZE: f32 = 40.0;
+
+pub fn setup_joystick_ui(mut commands: Commands) {
+    let ring = JOYSTICK_RADIUS * 2.0;
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Px(ring),
+                height: Val::Px(ring),
+                border: UiRect::all(Val::Px(3.0)),
+                ..default()
+            },
+            BorderColor(Color::srgba(0.0, 0.0, 0.0, 0.4)),
+            BorderRadius::MAX,
+            Visibility::Hidden,
+            JoystickBase,
+            StateScoped(InGame),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Node { position_type: PositionType::Absolute, width: Val::Px(KNOB_SIZE), height: Val::Px(KNOB_SIZE), ..default() },
+                BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.4)),
+                BorderRadius::MAX,
+                JoystickKnob,
+            ));
+        });
+}
+
+pub fn update_joystick_ui_system(
+    joystick: Res<VirtualJoystick>,
+    mut base: Query<(&mut Node, &mut Visibility), (With<JoystickBase>, Without<JoystickKnob>)>,
+    mut knob: Query<&mut Node, With<JoystickKnob>>,
+) {
+    if !joystick.is_changed() { return }
+    for (mut node, mut visibility) in &mut base {
+        *visibility = if joystick.touch.is_some() { Visibility::Inherited } else { Visibility::Hidden };
+        node.left = Val::Px(joystick.origin.x - JOYSTICK_RADIUS);
+        node.top = Val::Px(joystick.origin.y - JOYSTICK_RADIUS);
+    }
+    // Knob position inside the ring, back in window coordinates.
+    let offset = joystick.direction * Vec2::new(1.0, -1.0) * JOYSTICK_RADIUS + Vec2::splat(JOYSTICK_RADIUS - KNOB_SIZE / 2.0);
+    for mut node in &mut knob {
+        node.left = Val::Px(offset.x);
+        node.top = Val::Px(offset.y);
+    }
+}